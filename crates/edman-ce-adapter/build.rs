@@ -1,6 +1,7 @@
+use std::io::Write;
 use std::path::{Path, PathBuf};
 
-use typeshare_core::{language::Language, parser::ParsedData};
+use typeshare_core::{language::Language, parser::ParsedData, rust_types::RustEnumVariant};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let out_dir = PathBuf::from(std::env::var("OUT_DIR")?);
@@ -13,6 +14,23 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .enum_attribute(".", "#[::typeshare::typeshare]")
         .message_attribute(".", "#[derive(::serde::Serialize, ::serde::Deserialize)]")
         .enum_attribute(".", "#[derive(::serde::Serialize, ::serde::Deserialize)]")
+        // typeshare rejects 64-bit integers, but JSON carries them as plain numbers anyway
+        .field_attribute(
+            "config.Config.save_quota_bytes",
+            "#[typeshare(serialized_as = \"number\")]",
+        )
+        .field_attribute(
+            "config.OriginQuota.bytes",
+            "#[typeshare(serialized_as = \"number\")]",
+        )
+        .field_attribute(
+            "chrome_extension.ReportProgressRequest.bytes_received",
+            "#[typeshare(serialized_as = \"number\")]",
+        )
+        .field_attribute(
+            "chrome_extension.ReportProgressRequest.total_bytes",
+            "#[typeshare(serialized_as = \"number\")]",
+        )
         .protoc_arg("--proto_path")
         .protoc_arg(proto_dir.to_str().unwrap())
         .protoc_arg("--experimental_allow_proto3_optional")
@@ -37,7 +55,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut typescript = typeshare_core::language::TypeScript::default();
     typescript.generate_types(&mut type_file, &parsed_data)?;
 
+    let mut client_file = std::fs::File::create(out_dir.join("generated-client.ts"))?;
+    generate_client(&mut client_file, &parsed_data)?;
+
+    // Any of these disables the default of rerunning on every change in the package,
+    // so the sources that typeshare reads have to be listed as well
     println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=../../proto");
     Ok(())
 }
 
@@ -53,3 +78,156 @@ fn parse_files<P: AsRef<Path>>(paths: &[P]) -> ParsedData {
             identity
         })
 }
+
+/// Writes a client with one method for each `NativeMessageKinds` variant, which resolves
+/// to the `NativeResultKinds` variant of the same `type`.
+fn generate_client(
+    out: &mut impl Write,
+    parsed_data: &ParsedData,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let variants = |name: &str| {
+        parsed_data
+            .enums
+            .iter()
+            .find(|rust_enum| rust_enum.shared().id.original == name)
+            .map(|rust_enum| &rust_enum.shared().variants[..])
+            .ok_or_else(|| format!("{} is not exported with typeshare", name))
+    };
+    let messages = variants("NativeMessageKinds")?;
+    let results = variants("NativeResultKinds")?;
+
+    for message in messages {
+        let id = &message.shared().id;
+        if !results
+            .iter()
+            .any(|result| result.shared().id.renamed == id.renamed)
+        {
+            return Err(format!(
+                "NativeMessageKinds::{} has no reply of the same type in NativeResultKinds",
+                id.original
+            )
+            .into());
+        }
+    }
+
+    writeln!(
+        out,
+        "/*\n Generated by the build script of edman-ce-adapter\n*/\n"
+    )?;
+    writeln!(
+        out,
+        "import {{ NativeMessageKinds, NativeResultKinds }} from \"./ce-adapter\";\n"
+    )?;
+    writeln!(out, "/** `type` of every message the host understands */")?;
+    writeln!(
+        out,
+        "export type NativeType = NativeMessageKinds[\"type\"];\n"
+    )?;
+
+    writeln!(out, "/** Data sent with each type of message */")?;
+    writeln!(out, "export interface NativeRequests {{")?;
+    for kind in messages {
+        let name = &kind.shared().id.renamed;
+        writeln!(
+            out,
+            "\t{}: Extract<NativeMessageKinds, {{ type: \"{}\" }}>[\"data\"];",
+            name, name
+        )?;
+    }
+    writeln!(out, "}}\n")?;
+
+    writeln!(out, "/** Reply to each type of message */")?;
+    writeln!(out, "export interface NativeReplies {{")?;
+    for kind in messages {
+        let name = &kind.shared().id.renamed;
+        writeln!(
+            out,
+            "\t{}: Extract<NativeResultKinds, {{ type: \"{}\" }}>;",
+            name, name
+        )?;
+    }
+    writeln!(out, "}}\n")?;
+
+    writeln!(
+        out,
+        "/** Sends a message to the native host and resolves to its reply */"
+    )?;
+    writeln!(out, "export interface NativeTransport {{")?;
+    writeln!(out, "\tsendNativeMessage<T extends NativeType>(")?;
+    writeln!(out, "\t\ttype: T,")?;
+    writeln!(out, "\t\tdata: NativeRequests[T],")?;
+    writeln!(out, "\t): Promise<NativeReplies[T]>;")?;
+    writeln!(out, "}}\n")?;
+
+    writeln!(out, "/** One method for each type of message */")?;
+    writeln!(out, "export class NativeClient {{")?;
+    writeln!(
+        out,
+        "\tpublic constructor(private readonly transport: NativeTransport) {{}}"
+    )?;
+    for kind in messages {
+        let shared = kind.shared();
+        let name = &shared.id.renamed;
+        writeln!(out)?;
+        write_comments(out, &shared.comments)?;
+        if let RustEnumVariant::Unit(_) = kind {
+            writeln!(
+                out,
+                "\tpublic {}(): Promise<NativeReplies[\"{}\"]> {{",
+                camel_case(name),
+                name
+            )?;
+            writeln!(
+                out,
+                "\t\treturn this.transport.sendNativeMessage(\"{}\", undefined);",
+                name
+            )?;
+        } else {
+            writeln!(
+                out,
+                "\tpublic {}(data: NativeRequests[\"{}\"]): Promise<NativeReplies[\"{}\"]> {{",
+                camel_case(name),
+                name,
+                name
+            )?;
+            writeln!(
+                out,
+                "\t\treturn this.transport.sendNativeMessage(\"{}\", data);",
+                name
+            )?;
+        }
+        writeln!(out, "\t}}")?;
+    }
+    writeln!(out, "}}")?;
+
+    Ok(())
+}
+
+/// Writes doc comments the way typeshare does, indented for a class member
+fn write_comments(out: &mut impl Write, comments: &[String]) -> std::io::Result<()> {
+    match comments {
+        [] => Ok(()),
+        [line] => writeln!(out, "\t/** {} */", line),
+        lines => {
+            writeln!(out, "\t/**")?;
+            for line in lines {
+                writeln!(out, "\t * {}", line)?;
+            }
+            writeln!(out, "\t */")
+        }
+    }
+}
+
+/// `register_file` -> `registerFile`
+fn camel_case(snake_case: &str) -> String {
+    let mut parts = snake_case.split('_');
+    let mut name = parts.next().unwrap_or_default().to_owned();
+    for part in parts {
+        let mut chars = part.chars();
+        if let Some(first) = chars.next() {
+            name.extend(first.to_uppercase());
+            name.push_str(chars.as_str());
+        }
+    }
+    name
+}
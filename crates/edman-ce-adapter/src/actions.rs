@@ -0,0 +1,182 @@
+use std::path::{Path, PathBuf};
+
+/// One change that installing or uninstalling makes to the filesystem or the registry.
+///
+/// Installers plan every action first, so that `--dry-run` can show them without
+/// touching anything.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Action {
+    CreateDir(PathBuf),
+    /// Moves a file out of the way that has the name of our manifest, but was not installed by edman
+    Backup {
+        path: PathBuf,
+        backup: PathBuf,
+    },
+    Remove(PathBuf),
+    Write {
+        path: PathBuf,
+        content: String,
+    },
+    #[cfg(unix)]
+    Symlink {
+        target: PathBuf,
+        link: PathBuf,
+    },
+    #[cfg(windows)]
+    SetRegistryKey {
+        system: bool,
+        key: String,
+        manifest_path: PathBuf,
+    },
+    #[cfg(windows)]
+    DeleteRegistryKey {
+        system: bool,
+        key: String,
+    },
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Action::CreateDir(path) => write!(f, "create directory {}", path.display()),
+            Action::Backup { path, backup } => {
+                write!(f, "back up {} to {}", path.display(), backup.display())
+            }
+            Action::Remove(path) => write!(f, "remove {}", path.display()),
+            Action::Write { path, .. } => write!(f, "write {}", path.display()),
+            #[cfg(unix)]
+            Action::Symlink { target, link } => {
+                write!(f, "link {} to {}", link.display(), target.display())
+            }
+            #[cfg(windows)]
+            Action::SetRegistryKey {
+                system,
+                key,
+                manifest_path,
+            } => write!(
+                f,
+                r"set {}\{} to {}",
+                registry_root_name(*system),
+                key,
+                manifest_path.display()
+            ),
+            #[cfg(windows)]
+            Action::DeleteRegistryKey { system, key } => write!(
+                f,
+                r"delete registry key {}\{}",
+                registry_root_name(*system),
+                key
+            ),
+        }
+    }
+}
+
+impl Action {
+    fn apply(&self) -> std::io::Result<()> {
+        match self {
+            Action::CreateDir(path) => std::fs::create_dir_all(path),
+            Action::Backup { path, backup } => std::fs::rename(path, backup),
+            Action::Remove(path) => std::fs::remove_file(path),
+            Action::Write { path, content } => std::fs::write(path, content),
+            #[cfg(unix)]
+            Action::Symlink { target, link } => std::os::unix::fs::symlink(target, link),
+            #[cfg(windows)]
+            Action::SetRegistryKey {
+                system,
+                key,
+                manifest_path,
+            } => {
+                let (key, _) = registry_root(*system).create_subkey(key)?;
+                key.set_value("", &manifest_path.as_os_str())
+            }
+            #[cfg(windows)]
+            Action::DeleteRegistryKey { system, key } => registry_root(*system).delete_subkey(key),
+        }
+    }
+}
+
+/// Carries out the actions in order, or only prints them if `dry_run` is set.
+pub fn apply(actions: &[Action], dry_run: bool) -> std::io::Result<()> {
+    if dry_run {
+        if actions.is_empty() {
+            println!("Nothing to do.");
+        }
+        for action in actions {
+            println!("Would {}", action);
+        }
+        return Ok(());
+    }
+
+    for action in actions {
+        action.apply()?;
+    }
+    Ok(())
+}
+
+/// Adds `action` unless the same one is already planned.
+pub fn push_once(actions: &mut Vec<Action>, action: Action) {
+    if !actions.contains(&action) {
+        actions.push(action);
+    }
+}
+
+/// Plans to create the parent directory of `path` if it does not exist yet.
+pub fn create_parent_dir(actions: &mut Vec<Action>, path: &Path) {
+    if let Some(parent) = path.parent() {
+        if !parent.is_dir() {
+            push_once(actions, Action::CreateDir(parent.to_owned()));
+        }
+    }
+}
+
+/// Plans to write `content` to `path`, unless it is already there.
+pub fn write_file(actions: &mut Vec<Action>, path: &Path, content: &str) {
+    // A link is replaced by the file, even if it leads to the same content
+    let is_file = path
+        .symlink_metadata()
+        .map_or(false, |metadata| metadata.is_file());
+    if is_file && std::fs::read_to_string(path).map_or(false, |current| current == content) {
+        return;
+    }
+    create_parent_dir(actions, path);
+    push_once(
+        actions,
+        Action::Write {
+            path: path.to_owned(),
+            content: content.to_owned(),
+        },
+    );
+}
+
+/// First of `<path>.bak`, `<path>.bak.1`, ... that does not exist yet
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".bak");
+    let mut candidate = PathBuf::from(&backup);
+    let mut i = 1;
+    while candidate.symlink_metadata().is_ok() {
+        candidate = PathBuf::from(format!("{}.{}", backup.to_string_lossy(), i));
+        i += 1;
+    }
+    candidate
+}
+
+#[cfg(windows)]
+pub fn registry_root(system: bool) -> winreg::RegKey {
+    use winreg::enums::{HKEY_CURRENT_USER, HKEY_LOCAL_MACHINE};
+
+    winreg::RegKey::predef(if system {
+        HKEY_LOCAL_MACHINE
+    } else {
+        HKEY_CURRENT_USER
+    })
+}
+
+#[cfg(windows)]
+fn registry_root_name(system: bool) -> &'static str {
+    if system {
+        "HKEY_LOCAL_MACHINE"
+    } else {
+        "HKEY_CURRENT_USER"
+    }
+}
@@ -1,16 +1,23 @@
 use std::io::Write;
+use std::path::Path;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let generated_ts = include_str!(concat!(env!("OUT_DIR"), "/generated.ts"));
+    let generated_client = include_str!(concat!(env!("OUT_DIR"), "/generated-client.ts"));
 
     let ts = format!(
-        "export const EDMAN_UNIQUE_NAME = \"{}\";\n\n{}",
+        "export const EDMAN_UNIQUE_NAME = \"{}\";\nexport const NATIVE_PROTOCOL_VERSION = {};\n\n{}",
         utils::EDMAN_UNIQUE_NAME,
+        utils::NATIVE_PROTOCOL_VERSION,
         generated_ts
     );
 
     let file = std::env::args().nth(1).unwrap();
-    std::fs::File::create(file)?.write_all(ts.as_bytes())?;
+    std::fs::File::create(&file)?.write_all(ts.as_bytes())?;
+
+    // The client imports the types from "./ce-adapter"
+    let client_file = Path::new(&file).with_file_name("ce-adapter-client.ts");
+    std::fs::File::create(client_file)?.write_all(generated_client.as_bytes())?;
 
     Ok(())
 }
@@ -0,0 +1,111 @@
+use std::path::PathBuf;
+
+use crate::config;
+
+/// Browser extension that launched this process.
+///
+/// Each browser family passes a different set of arguments to a native host:
+/// Chromium based browsers the origin of the extension (plus `--parent-window`
+/// on Windows), Firefox the path to the app manifest and the extension ID.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Caller {
+    Chromium {
+        /// `chrome-extension://<id>/`
+        origin: String,
+    },
+    Firefox {
+        manifest_path: PathBuf,
+        extension_id: String,
+    },
+}
+
+impl Caller {
+    /// Recognizes the calling convention from the positional arguments.
+    ///
+    /// Returns `None` if they do not match any browser, e.g. when started from a shell.
+    pub fn from_args(first: Option<&str>, second: Option<&str>) -> Option<Self> {
+        match (first?, second) {
+            (origin, None) if origin.starts_with("chrome-extension://") => Some(Caller::Chromium {
+                origin: origin.to_owned(),
+            }),
+            (manifest_path, Some(extension_id))
+                if manifest_path.ends_with(".json") && !extension_id.is_empty() =>
+            {
+                Some(Caller::Firefox {
+                    manifest_path: PathBuf::from(manifest_path),
+                    extension_id: extension_id.to_owned(),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Origin or extension ID, as listed in the allow-lists of the config
+    pub fn identity(&self) -> &str {
+        match self {
+            Caller::Chromium { origin } => origin,
+            Caller::Firefox { extension_id, .. } => extension_id,
+        }
+    }
+
+    /// Checks the identity against the allow-list of the browser family.
+    pub fn is_allowed(&self, config: &config::Config) -> bool {
+        match self {
+            Caller::Chromium { origin } => config.allowed_origins.contains(origin),
+            Caller::Firefox { extension_id, .. } => {
+                config.allowed_extensions.contains(extension_id)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::caller::Caller;
+    use crate::config;
+
+    #[test]
+    fn recognize_browsers() {
+        assert_eq!(
+            Caller::from_args(Some("chrome-extension://abc/"), None),
+            Some(Caller::Chromium {
+                origin: "chrome-extension://abc/".to_string()
+            })
+        );
+        assert_eq!(
+            Caller::from_args(
+                Some("/usr/lib/mozilla/native-messaging-hosts/edman.json"),
+                Some("edman@example.com")
+            ),
+            Some(Caller::Firefox {
+                manifest_path: PathBuf::from("/usr/lib/mozilla/native-messaging-hosts/edman.json"),
+                extension_id: "edman@example.com".to_string()
+            })
+        );
+        assert_eq!(Caller::from_args(None, None), None);
+        assert_eq!(Caller::from_args(Some("somewhere"), None), None);
+    }
+
+    #[test]
+    fn allow_list_per_browser() {
+        let config = config::Config {
+            allowed_origins: vec!["chrome-extension://abc/".to_string()],
+            allowed_extensions: vec!["edman@example.com".to_string()],
+            ..Default::default()
+        };
+
+        let firefox = Caller::Firefox {
+            manifest_path: PathBuf::from("edman.json"),
+            extension_id: "edman@example.com".to_string(),
+        };
+        assert!(firefox.is_allowed(&config));
+
+        // An extension ID does not pass as a Chromium origin
+        let chromium = Caller::Chromium {
+            origin: "edman@example.com".to_string(),
+        };
+        assert!(!chromium.is_allowed(&config));
+    }
+}
@@ -0,0 +1,92 @@
+use std::marker::PhantomData;
+
+use bytes::{Bytes, BytesMut};
+use serde::{de::DeserializeOwned, Serialize};
+use tokio_util::codec::{Decoder, Encoder, LengthDelimitedCodec};
+
+/// Browsers never send messages larger than this to a native host
+pub const MAX_INCOMING_SIZE: usize = 64 * 1024 * 1024;
+
+/// Native messaging framing: JSON prefixed with its length as a native-endian `u32`.
+///
+/// Decodes frames into `In` and encodes `Out` into frames.
+/// A stream that ends in the middle of a frame yields an error instead of a
+/// clean end of stream.
+pub struct NativeCodec<In, Out> {
+    frames: LengthDelimitedCodec,
+    _types: PhantomData<fn(Out) -> In>,
+}
+
+impl<In, Out> Default for NativeCodec<In, Out> {
+    fn default() -> Self {
+        Self {
+            frames: LengthDelimitedCodec::builder()
+                .native_endian()
+                .length_field_length(4)
+                .max_frame_length(MAX_INCOMING_SIZE)
+                .new_codec(),
+            _types: PhantomData,
+        }
+    }
+}
+
+impl<In: DeserializeOwned, Out> Decoder for NativeCodec<In, Out> {
+    type Item = In;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.frames.decode(src)? {
+            Some(frame) => Ok(Some(serde_json::from_slice(&frame)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<In, Out: Serialize> Encoder<Out> for NativeCodec<In, Out> {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, item: Out, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let frame = serde_json::to_vec(&item)?;
+        self.frames.encode(Bytes::from(frame), dst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_stream::StreamExt;
+    use tokio_util::codec::FramedRead;
+
+    use crate::codec::NativeCodec;
+
+    fn frame(content: &str) -> Vec<u8> {
+        let mut frame = (content.len() as u32).to_ne_bytes().to_vec();
+        frame.extend_from_slice(content.as_bytes());
+        frame
+    }
+
+    #[tokio::test]
+    async fn clean_eof() {
+        let input = frame("\"a\"");
+        let mut reader = FramedRead::new(&input[..], NativeCodec::<String, ()>::default());
+
+        assert_eq!(reader.next().await.unwrap().unwrap(), "a");
+        assert!(reader.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn truncated_frame() {
+        let mut input = frame("\"a\"");
+        input.pop();
+        let mut reader = FramedRead::new(&input[..], NativeCodec::<String, ()>::default());
+
+        assert!(reader.next().await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn oversized_frame() {
+        let input = u32::MAX.to_ne_bytes();
+        let mut reader = FramedRead::new(&input[..], NativeCodec::<String, ()>::default());
+
+        assert!(reader.next().await.unwrap().is_err());
+    }
+}
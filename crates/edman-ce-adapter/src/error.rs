@@ -0,0 +1,158 @@
+use serde::{Deserialize, Serialize};
+
+/// Stable identifiers the extension can branch on
+#[typeshare::typeshare]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NativeErrorCode {
+    /// The calling extension is not in the allow-list of the profile
+    OriginNotAllowed,
+    /// The extension and the native host cannot talk to each other
+    ProtocolMismatch,
+    /// The message has a `type` the native host does not know
+    UnknownMessage,
+    /// The message could not be parsed
+    InvalidMessage,
+    /// A download or save path is outside of the allowed directories
+    PathInvalid,
+    /// A value of the message is not acceptable, e.g. a malformed URL
+    InvalidArgument,
+    NotFound,
+    AlreadyExists,
+    PermissionDenied,
+    QuotaExceeded,
+    /// edman-main cannot be reached
+    BackendUnavailable,
+    IoError,
+    Internal,
+}
+
+#[typeshare::typeshare]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct NativeError {
+    pub code: NativeErrorCode,
+    pub message: String,
+    /// Whether sending the same message again may succeed
+    pub retryable: bool,
+    pub details: Option<String>,
+}
+
+impl NativeError {
+    pub fn new(code: NativeErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            retryable: false,
+            details: None,
+        }
+    }
+}
+
+impl std::fmt::Display for NativeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for NativeError {}
+
+impl From<tonic::Status> for NativeError {
+    fn from(status: tonic::Status) -> Self {
+        use tonic::Code;
+
+        let (code, retryable) = match status.code() {
+            Code::InvalidArgument if status.metadata().contains_key(utils::PATH_INVALID_KEY) => {
+                (NativeErrorCode::PathInvalid, false)
+            }
+            Code::InvalidArgument | Code::OutOfRange => (NativeErrorCode::InvalidArgument, false),
+            Code::NotFound => (NativeErrorCode::NotFound, false),
+            Code::AlreadyExists => (NativeErrorCode::AlreadyExists, false),
+            Code::PermissionDenied | Code::Unauthenticated => {
+                (NativeErrorCode::PermissionDenied, false)
+            }
+            Code::ResourceExhausted => (NativeErrorCode::QuotaExceeded, false),
+            Code::Unavailable | Code::DeadlineExceeded | Code::Cancelled => {
+                (NativeErrorCode::BackendUnavailable, true)
+            }
+            Code::Aborted => (NativeErrorCode::Internal, true),
+            Code::Unimplemented => (NativeErrorCode::ProtocolMismatch, false),
+            _ => (NativeErrorCode::Internal, false),
+        };
+
+        Self {
+            code,
+            message: status.message().to_string(),
+            retryable,
+            details: Some(format!("gRPC status {:?}", status.code())),
+        }
+    }
+}
+
+impl From<std::io::Error> for NativeError {
+    fn from(err: std::io::Error) -> Self {
+        use std::io::ErrorKind;
+
+        let (code, retryable) = match err.kind() {
+            ErrorKind::NotFound => (NativeErrorCode::NotFound, false),
+            ErrorKind::AlreadyExists => (NativeErrorCode::AlreadyExists, false),
+            ErrorKind::PermissionDenied => (NativeErrorCode::PermissionDenied, false),
+            ErrorKind::InvalidInput => (NativeErrorCode::InvalidArgument, false),
+            ErrorKind::Interrupted | ErrorKind::TimedOut | ErrorKind::WouldBlock => {
+                (NativeErrorCode::IoError, true)
+            }
+            _ => (NativeErrorCode::IoError, false),
+        };
+
+        Self {
+            code,
+            message: err.to_string(),
+            retryable,
+            details: Some(format!("I/O error {:?}", err.kind())),
+        }
+    }
+}
+
+impl From<tonic::transport::Error> for NativeError {
+    fn from(err: tonic::transport::Error) -> Self {
+        Self {
+            retryable: true,
+            ..Self::new(NativeErrorCode::BackendUnavailable, err.to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::{NativeError, NativeErrorCode};
+
+    #[test]
+    fn from_status() {
+        let err = NativeError::from(tonic::Status::unavailable("connection refused"));
+        assert_eq!(err.code, NativeErrorCode::BackendUnavailable);
+        assert!(err.retryable);
+
+        let err = NativeError::from(tonic::Status::resource_exhausted("quota"));
+        assert_eq!(err.code, NativeErrorCode::QuotaExceeded);
+        assert!(!err.retryable);
+        assert_eq!(err.message, "quota");
+
+        let err = NativeError::from(tonic::Status::invalid_argument("Invalid url"));
+        assert_eq!(err.code, NativeErrorCode::InvalidArgument);
+
+        let mut status = tonic::Status::invalid_argument("savePath must not be empty.");
+        status
+            .metadata_mut()
+            .insert(utils::PATH_INVALID_KEY, "true".parse().unwrap());
+        assert_eq!(NativeError::from(status).code, NativeErrorCode::PathInvalid);
+    }
+
+    #[test]
+    fn serialize() {
+        let err = NativeError::new(NativeErrorCode::OriginNotAllowed, "nope");
+        assert_eq!(
+            serde_json::to_string(&err).unwrap(),
+            "{\"code\":\"origin_not_allowed\",\"message\":\"nope\",\"retryable\":false,\"details\":null}"
+        );
+    }
+}
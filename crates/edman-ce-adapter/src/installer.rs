@@ -1,87 +1,624 @@
-use std::{io::Write, path::PathBuf};
+use std::path::{Path, PathBuf};
 
-use crate::manifest::{BrowserKind, BrowserStrain};
+use clap::ValueEnum;
+use serde::Serialize;
+use utils::locations::{self, Location, LocationKind};
 
-pub fn install(option: &BrowserKind, manifest: String) -> Result<(), Box<dyn std::error::Error>> {
-    let manifest_path = match option.into() {
-        BrowserStrain::Chromium => utils::manifest_path_chromium(),
-        BrowserStrain::Firefox => utils::manifest_path_firefox(),
+use crate::actions::{self, Action};
+use crate::config;
+use crate::manifest::{AppManifest, BrowserKind, BrowserStrain};
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum OutputFormat {
+    Table,
+    Json,
+}
+
+/// Whether the manifest at one location of a browser is installed and up to date
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct InstallStatus {
+    pub browser: &'static str,
+    pub location: LocationKind,
+    /// The browser is installed or has been used at this location
+    pub detected: bool,
+    /// A manifest for edman is registered with the browser
+    pub installed: bool,
+    /// The registered manifest launches this executable
+    pub current_exe: bool,
+    /// The registered manifest allows exactly the extensions of the current configuration
+    pub current_origins: bool,
+}
+
+/// Manifest file written for a browser, which edman-main keeps up to date
+#[derive(Debug)]
+pub struct Installed {
+    pub browser: BrowserKind,
+    pub manifest_file: PathBuf,
+}
+
+/// Outcome of installing for every browser found
+pub struct InstallAll {
+    pub installed: Vec<Installed>,
+    /// Names of the browsers for which the installation failed
+    pub failed: Vec<&'static str>,
+}
+
+/// Installs the manifest at every location where the browser is found, or at its
+/// regular location if it is found nowhere.
+///
+/// Returns the manifest files written, none if `dry_run` is set.
+pub fn install(
+    option: &BrowserKind,
+    manifest: String,
+    system: bool,
+    dry_run: bool,
+) -> Result<Vec<Installed>, Box<dyn std::error::Error>> {
+    let locations = known_locations(option, system)?;
+    let regular = &locations[0];
+
+    let mut targets: Vec<_> = locations
+        .iter()
+        .filter(|location| location.is_detected())
+        .collect();
+    if targets.is_empty() {
+        targets.push(regular);
+    }
+
+    let mut actions = vec![];
+    let mut installed = vec![];
+    for location in &targets {
+        let manifest_file = locations::manifest_file(option, location)?;
+        plan_install(&mut actions, location, &manifest_file, &manifest);
+        if !installed
+            .iter()
+            .any(|entry: &Installed| entry.manifest_file == manifest_file)
+        {
+            installed.push(Installed {
+                browser: *option,
+                manifest_file,
+            });
+        }
+    }
+    actions::apply(&actions, dry_run)?;
+    if dry_run {
+        return Ok(vec![]);
+    }
+
+    for location in targets {
+        println!(
+            "Edman native messaging manifest was successfully installed for {} ({}).",
+            option.name(),
+            location.kind.name()
+        );
+    }
+
+    Ok(installed)
+}
+
+/// Installs the manifest for every supported browser that is found on this machine.
+///
+/// A failure for one browser does not stop the others.
+pub fn install_all(
+    config: &config::Config,
+    system: bool,
+    dry_run: bool,
+) -> Result<InstallAll, Box<dyn std::error::Error>> {
+    let detected: Vec<_> = BrowserKind::value_variants()
+        .iter()
+        .filter(|browser| {
+            locations::locations(browser, system)
+                .iter()
+                .any(Location::is_detected)
+        })
+        .collect();
+    if detected.is_empty() {
+        return Err("No supported browser was found.".into());
+    }
+    if system {
+        let unknown: Vec<_> = BrowserKind::value_variants()
+            .iter()
+            .filter(|browser| locations::locations(browser, true).is_empty())
+            .map(BrowserKind::name)
+            .collect();
+        if !unknown.is_empty() {
+            eprintln!(
+                "No system-wide manifest location is known for {}, which are skipped.",
+                unknown.join(", ")
+            );
+        }
+    }
+
+    let mut outcome = InstallAll {
+        installed: vec![],
+        failed: vec![],
     };
-    utils::create_parent_dirs(&manifest_path)?;
-    std::fs::File::create(&manifest_path)?.write_all(manifest.as_bytes())?;
+    for browser in detected {
+        let manifest = serde_json::to_string_pretty(&AppManifest::new(browser, config)?)?;
+        match install(browser, manifest, system, dry_run) {
+            Ok(installed) => outcome.installed.extend(installed),
+            Err(err) => {
+                eprintln!(
+                    "Cannot install the manifest for {}: {}",
+                    browser.name(),
+                    err
+                );
+                outcome.failed.push(browser.name());
+            }
+        }
+    }
+
+    Ok(outcome)
+}
+
+/// Removes the manifest from every location of the browser, along with the file
+/// it points to unless another browser still uses that.
+///
+/// Files of the same name that edman did not install are left alone, and so are
+/// registrations that another installed browser reads as well.
+pub fn uninstall(
+    option: BrowserKind,
+    system: bool,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut actions = vec![];
+    let mut kept_shared = false;
+    for location in known_locations(&option, system)? {
+        let manifest_file = locations::manifest_file(&option, &location)?;
+        match registered(&location, &manifest_file) {
+            Registered::Nothing => continue,
+            Registered::Foreign => {
+                eprintln!(
+                    "The manifest for {} ({}) was not installed by edman and is left in place.",
+                    option.name(),
+                    location.kind.name()
+                );
+                continue;
+            }
+            Registered::Current | Registered::Outdated => {}
+        }
+
+        let sharing = shared_with(&option, &location, system);
+        if !sharing.is_empty() {
+            eprintln!(
+                "The manifest for {} ({}) is also read by {} and is left in place.",
+                option.name(),
+                location.kind.name(),
+                sharing.join(", ")
+            );
+            kept_shared = true;
+            continue;
+        }
+
+        cfg_if::cfg_if! {
+            if #[cfg(windows)] {
+                actions.push(Action::DeleteRegistryKey {
+                    system: location.kind == LocationKind::System,
+                    key: location.registry_key.to_owned(),
+                });
+            } else if #[cfg(unix)] {
+                actions.push(Action::Remove(location.manifest_path.to_owned()));
+            }
+        }
+        if manifest_file.is_file() && !is_used_by_others(&option, &location, &manifest_file, system)
+        {
+            actions::push_once(&mut actions, Action::Remove(manifest_file));
+        }
+    }
+    if actions.is_empty() && kept_shared {
+        return Ok(());
+    }
+    if actions.is_empty() {
+        return Err(format!("No manifest of edman is installed for {}.", option.name()).into());
+    }
 
+    actions::apply(&actions, dry_run)?;
+    if !dry_run {
+        println!("Edman native messaging manifest was successfully uninstalled.");
+    }
+
+    Ok(())
+}
+
+/// Checks the manifest of every supported browser against the current configuration.
+///
+/// Sandboxed locations are only listed if they are in use.
+pub fn status(config: &config::Config, system: bool) -> std::io::Result<Vec<InstallStatus>> {
+    let mut statuses = vec![];
+    for browser in BrowserKind::value_variants() {
+        let expected = serde_json::to_value(AppManifest::new(browser, config)?)?;
+        for (i, location) in locations::locations(browser, system).iter().enumerate() {
+            let detected = location.is_detected();
+            let installed = read_installed(location);
+            if i > 0 && !detected && installed.is_none() {
+                continue;
+            }
+
+            statuses.push(manifest_status(
+                browser,
+                location.kind,
+                detected,
+                installed.as_ref(),
+                &expected,
+            ));
+        }
+    }
+    Ok(statuses)
+}
+
+pub fn print_status(statuses: &[InstallStatus], format: OutputFormat) -> serde_json::Result<()> {
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(statuses)?),
+        OutputFormat::Table => {
+            let yes_no = |value: bool| if value { "yes" } else { "no" };
+            println!("BROWSER        LOCATION  DETECTED  INSTALLED  CURRENT EXE  CURRENT ORIGINS");
+            for status in statuses {
+                println!(
+                    "{:<14} {:<9} {:<9} {:<10} {:<12} {}",
+                    status.browser,
+                    status.location.name(),
+                    yes_no(status.detected),
+                    yes_no(status.installed),
+                    yes_no(status.current_exe),
+                    yes_no(status.current_origins)
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn manifest_status(
+    browser: &BrowserKind,
+    location: LocationKind,
+    detected: bool,
+    installed: Option<&serde_json::Value>,
+    expected: &serde_json::Value,
+) -> InstallStatus {
+    let allow_list_key = match browser.into() {
+        BrowserStrain::Chromium => "allowed_origins",
+        BrowserStrain::Firefox => "allowed_extensions",
+    };
+    // The order of the allow-list does not matter to the browser
+    fn allow_list<'a>(manifest: &'a serde_json::Value, key: &str) -> Vec<&'a str> {
+        let mut list: Vec<_> = manifest
+            .get(key)
+            .and_then(|list| list.as_array())
+            .map(|list| list.iter().filter_map(|entry| entry.as_str()).collect())
+            .unwrap_or_default();
+        list.sort_unstable();
+        list
+    }
+
+    InstallStatus {
+        browser: browser.name(),
+        location,
+        detected,
+        installed: installed.is_some(),
+        current_exe: installed.map_or(false, |manifest| {
+            manifest.get("path") == expected.get("path")
+        }),
+        current_origins: installed.map_or(false, |manifest| {
+            allow_list(manifest, allow_list_key) == allow_list(expected, allow_list_key)
+        }),
+    }
+}
+
+/// What is already registered under the name of our manifest at a location
+#[derive(Debug, PartialEq, Eq)]
+enum Registered {
+    Nothing,
+    /// Points at the file that edman would install
+    Current,
+    /// Left by an earlier installation of edman, or a link to a manifest that is gone
+    Outdated,
+    /// Belongs to some other program that uses our name
+    Foreign,
+}
+
+fn registered(location: &Location, manifest_file: &Path) -> Registered {
     cfg_if::cfg_if! {
         if #[cfg(windows)] {
-            let parent_key = get_registry(&option)?;
-            let (key, _) = parent_key.create_subkey(utils::EDMAN_UNIQUE_NAME)?;
-            key.set_value("", manifest_path.to_str().as_ref().unwrap())?;
+            let Ok(key) = actions::registry_root(location.kind == LocationKind::System)
+                .open_subkey(&location.registry_key)
+            else {
+                return Registered::Nothing;
+            };
+            let Ok(path) = key.get_value::<String, _>("") else {
+                return Registered::Outdated;
+            };
+            let path = &PathBuf::from(path);
+            if path == manifest_file {
+                return Registered::Current;
+            }
+            let is_copy = false;
         } else if #[cfg(unix)] {
-            let link_path = get_link_path(&option);
-            std::os::unix::fs::symlink(manifest_path, link_path)?;
+            let path = &location.manifest_path;
+            let Ok(metadata) = path.symlink_metadata() else {
+                return Registered::Nothing;
+            };
+            if metadata.is_symlink()
+                && std::fs::read_link(path).map_or(false, |target| target == manifest_file)
+            {
+                return Registered::Current;
+            }
+            let is_copy = metadata.is_file() && path == manifest_file;
         }
     }
 
-    println!("Edman native messaging manifest was successfully installed.");
+    match (is_edman_manifest(path), is_copy) {
+        (true, true) => Registered::Current,
+        (true, false) => Registered::Outdated,
+        (false, _) => Registered::Foreign,
+    }
+}
 
-    Ok(())
+/// Whether the file at `path` is a manifest that launches edman-ce-adapter.
+///
+/// A missing file counts as ours, since nothing is lost by replacing a link to it.
+fn is_edman_manifest(path: &Path) -> bool {
+    if !path.exists() {
+        return true;
+    }
+    let Some(manifest) = std::fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str::<serde_json::Value>(&content).ok())
+    else {
+        return false;
+    };
+
+    let exe_name = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.file_name().map(|name| name.to_owned()));
+    manifest.get("name").and_then(|name| name.as_str()) == Some(utils::EDMAN_UNIQUE_NAME)
+        && manifest
+            .get("path")
+            .and_then(|path| path.as_str())
+            .and_then(|path| Path::new(path).file_name())
+            .map(|name| name.to_owned())
+            == exe_name
 }
 
-pub fn uninstall(option: BrowserKind) -> Result<(), Box<dyn std::error::Error>> {
+/// Plans the actions that make `location` point at `manifest_file` holding `manifest`.
+///
+/// Nothing is planned for what is already in place.
+fn plan_install(
+    actions: &mut Vec<Action>,
+    location: &Location,
+    manifest_file: &Path,
+    manifest: &str,
+) {
+    let registered = registered(location, manifest_file);
+
     cfg_if::cfg_if! {
         if #[cfg(windows)] {
-            let parent_key = get_registry(&option)?;
-            parent_key.delete_subkey(utils::EDMAN_UNIQUE_NAME)?;
+            // The file that a foreign key points at is not ours to move, only the key is replaced
+            actions::write_file(actions, manifest_file, manifest);
+            if registered != Registered::Current {
+                actions.push(Action::SetRegistryKey {
+                    system: location.kind == LocationKind::System,
+                    key: location.registry_key.to_owned(),
+                    manifest_path: manifest_file.to_owned(),
+                });
+            }
         } else if #[cfg(unix)] {
-            let link_path = get_link_path(&option);
-            std::fs::remove_file(link_path)?;
+            let path = &location.manifest_path;
+            match registered {
+                Registered::Outdated => actions.push(Action::Remove(path.to_owned())),
+                Registered::Foreign => actions.push(Action::Backup {
+                    path: path.to_owned(),
+                    backup: actions::backup_path(path),
+                }),
+                Registered::Nothing | Registered::Current => {}
+            }
+
+            actions::write_file(actions, manifest_file, manifest);
+            if path != manifest_file && registered != Registered::Current {
+                actions::create_parent_dir(actions, path);
+                actions.push(Action::Symlink {
+                    target: manifest_file.to_owned(),
+                    link: path.to_owned(),
+                });
+            }
         }
     }
+}
 
-    println!("Edman native messaging manifest was successfully uninstalled.");
+/// Locations of `browser`, failing if there are none, as for the system-wide locations of
+/// some browsers.
+fn known_locations(
+    browser: &BrowserKind,
+    system: bool,
+) -> Result<Vec<Location>, Box<dyn std::error::Error>> {
+    let locations = locations::locations(browser, system);
+    if locations.is_empty() {
+        let kind = if system { "system-wide" } else { "per-user" };
+        return Err(format!(
+            "No {} manifest location is known for {}.",
+            kind,
+            browser.name()
+        )
+        .into());
+    }
+    Ok(locations)
+}
 
-    Ok(())
+/// Whether a location of another browser is installed with `manifest_file`.
+///
+/// Locations that share the registration of `removed` do not count, as that goes away.
+fn is_used_by_others(
+    option: &BrowserKind,
+    removed: &Location,
+    manifest_file: &Path,
+    system: bool,
+) -> bool {
+    BrowserKind::value_variants()
+        .iter()
+        .filter(|browser| *browser != option)
+        .any(|browser| {
+            locations::locations(browser, system)
+                .iter()
+                .any(|location| {
+                    !location.is_same_registration(removed)
+                        && locations::manifest_file(browser, location)
+                            .map_or(false, |file| file == manifest_file)
+                        && registered(location, manifest_file) == Registered::Current
+                })
+        })
+}
+
+/// Names of the other installed browsers that read the manifest registered at `location`.
+fn shared_with(option: &BrowserKind, location: &Location, system: bool) -> Vec<&'static str> {
+    BrowserKind::value_variants()
+        .iter()
+        .filter(|browser| *browser != option)
+        .filter(|browser| {
+            locations::locations(browser, system)
+                .iter()
+                .any(|other| other.is_detected() && other.is_same_registration(location))
+        })
+        .map(BrowserKind::name)
+        .collect()
 }
 
-#[cfg(unix)]
-fn get_link_path(option: &BrowserKind) -> PathBuf {
+/// Reads the manifest registered for edman, if any.
+fn read_installed(location: &Location) -> Option<serde_json::Value> {
     cfg_if::cfg_if! {
-        if #[cfg(target="macos")] {
-            let path = match option {
-                BrowserKind::Chrome => "Library/Application Support/Google/Chrome/NativeMessagingHosts",
-                BrowserKind::Chromium => "Library/Application Support/Chromium/NativeMessagingHosts",
-                BrowserKind::Vivaldi => "Library/Application Support/Vivaldi/NativeMessagingHosts",
-                BrowserKind::Firefox => "Library/Application Support/Mozilla/NativeMessagingHosts",
-            };
-        } else {
-            let path = match option {
-                BrowserKind::Chrome => ".config/chrome/NativeMessagingHosts",
-                BrowserKind::Chromium => ".config/chromium/NativeMessagingHosts",
-                BrowserKind::Vivaldi => ".config/vivaldi/NativeMessagingHosts",
-                BrowserKind::Firefox => ".mozilla/native-messaging-hosts",
-            };
+        if #[cfg(windows)] {
+            let key = actions::registry_root(location.kind == LocationKind::System)
+                .open_subkey(&location.registry_key)
+                .ok()?;
+            let manifest_path: String = key.get_value("").ok()?;
+        } else if #[cfg(unix)] {
+            let manifest_path = &location.manifest_path;
         }
-    };
-
-    let user_dir = directories::UserDirs::new()
-        .map(|user_dir| user_dir.home_dir().to_owned())
-        .expect("Could not retrieve home directory.");
+    }
 
-    user_dir
-        .join(path)
-        .join(format!("{}.json", utils::EDMAN_UNIQUE_NAME))
+    let content = std::fs::read_to_string(manifest_path).ok()?;
+    serde_json::from_str(&content).ok()
 }
 
-#[cfg(windows)]
-fn get_registry(option: &BrowserKind) -> std::io::Result<winreg::RegKey> {
-    use winreg::enums::*;
-    use winreg::RegKey;
-    let path = match option.into() {
-        BrowserStrain::Chromium => r"SOFTWARE\Google\Chrome\NativeMessagingHosts",
-        BrowserStrain::Firefox => r"SOFTWARE\Mozilla\NativeMessagingHosts",
-    };
+#[cfg(test)]
+mod tests {
+    use utils::locations::LocationKind;
+
+    use crate::installer::{manifest_status, InstallStatus};
+    use crate::manifest::BrowserKind;
 
-    let hkcu = RegKey::predef(HKEY_CURRENT_USER);
-    let (key, _) = hkcu.create_subkey(path)?;
+    #[test]
+    fn outdated_manifest() {
+        let expected = serde_json::json!({
+            "path": "/opt/edman/edman-ce-adapter",
+            "allowed_origins": ["chrome-extension://a/", "chrome-extension://b/"],
+        });
+        let installed = serde_json::json!({
+            "path": "/old/edman-ce-adapter",
+            "allowed_origins": ["chrome-extension://b/", "chrome-extension://a/"],
+        });
 
-    Ok(key)
+        assert_eq!(
+            manifest_status(
+                &BrowserKind::Chrome,
+                LocationKind::User,
+                true,
+                Some(&installed),
+                &expected
+            ),
+            InstallStatus {
+                browser: "chrome",
+                location: LocationKind::User,
+                detected: true,
+                installed: true,
+                current_exe: false,
+                current_origins: true,
+            }
+        );
+        assert!(
+            !manifest_status(
+                &BrowserKind::Firefox,
+                LocationKind::Flatpak,
+                false,
+                None,
+                &expected
+            )
+            .installed
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn foreign_manifest_is_backed_up() {
+        use utils::locations::Location;
+
+        use crate::actions::{self, Action};
+        use crate::installer::plan_install;
+
+        let dir = std::env::temp_dir().join(format!("edman-plan-install-{}", std::process::id()));
+        let location = Location {
+            kind: LocationKind::User,
+            detect_dir: dir.to_owned(),
+            manifest_path: dir.join("hosts/edman.json"),
+        };
+        let manifest_file = dir.join("own/manifest.json");
+        std::fs::create_dir_all(dir.join("hosts")).unwrap();
+        let foreign = serde_json::json!({
+            "name": utils::EDMAN_UNIQUE_NAME,
+            "path": "/usr/bin/another-host",
+        });
+        std::fs::write(&location.manifest_path, foreign.to_string()).unwrap();
+
+        let mut planned = vec![];
+        plan_install(&mut planned, &location, &manifest_file, "{}");
+        assert_eq!(
+            planned,
+            vec![
+                Action::Backup {
+                    path: location.manifest_path.to_owned(),
+                    backup: dir.join("hosts/edman.json.bak"),
+                },
+                Action::CreateDir(dir.join("own")),
+                Action::Write {
+                    path: manifest_file.to_owned(),
+                    content: "{}".to_string(),
+                },
+                Action::Symlink {
+                    target: manifest_file.to_owned(),
+                    link: location.manifest_path.to_owned(),
+                },
+            ]
+        );
+
+        // Installing again changes nothing
+        actions::apply(&planned, false).unwrap();
+        let mut planned = vec![];
+        plan_install(&mut planned, &location, &manifest_file, "{}");
+        assert_eq!(planned, vec![]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Installing for one of them must not point the key away from the manifest of another.
+    #[cfg(windows)]
+    #[test]
+    fn browsers_sharing_a_registration_share_the_manifest_file() {
+        use clap::ValueEnum;
+        use utils::locations;
+
+        for system in [false, true] {
+            for a in BrowserKind::value_variants() {
+                for b in BrowserKind::value_variants() {
+                    for location_a in locations::locations(a, system) {
+                        for location_b in locations::locations(b, system) {
+                            if location_a.is_same_registration(&location_b) {
+                                assert_eq!(
+                                    locations::manifest_file(a, &location_a).unwrap(),
+                                    locations::manifest_file(b, &location_b).unwrap()
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
 }
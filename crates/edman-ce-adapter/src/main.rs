@@ -1,11 +1,22 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use caller::Caller;
 use chrome_extension::download_manager_client::DownloadManagerClient;
-use clap::{Args, Parser};
-use manifest::{AppManifest, BrowserKind};
-use native_messaging::main_loop;
+use clap::{Args, Parser, Subcommand};
+use installer::OutputFormat;
+use manifest::{AppManifest, BrowserKind, BrowserStrain};
+use native_messaging::{main_loop, Session};
+use recording::Recorder;
 
+mod actions;
+mod caller;
+mod codec;
+mod error;
 mod installer;
 mod manifest;
 mod native_messaging;
+mod recording;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -15,6 +26,27 @@ struct Cli {
     #[arg(group = "input", long)]
     install: Option<BrowserKind>,
 
+    /// Install native messaging manifests to every supported browser found on this machine
+    #[arg(group = "input", long)]
+    install_all: bool,
+
+    /// Show for every supported browser whether the manifest is installed and up to date
+    #[arg(group = "input", long)]
+    status: bool,
+
+    /// Install to, uninstall from or check the system-wide locations instead of the per-user ones.
+    /// Not every browser has one on every platform
+    #[arg(long)]
+    system: bool,
+
+    /// Print what --install, --install-all or --uninstall would change without changing it
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Output format of --status
+    #[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+    format: OutputFormat,
+
     /// Uninstall native messaging manifest installed for edman in the specified browser
     #[arg(group = "input", long)]
     uninstall: Option<BrowserKind>,
@@ -23,15 +55,50 @@ struct Cli {
     #[arg(group = "input", long)]
     manifest: Option<BrowserKind>,
 
+    /// Maximum number of browser requests processed at the same time
+    #[arg(
+        long,
+        default_value_t = 8,
+        env = "EDMAN_MAX_IN_FLIGHT",
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    max_in_flight: usize,
+
+    /// Append every native message received and sent to this file, for the replay command.
+    /// Browsers do not pass options to the host, so it is usually set through the environment.
+    #[arg(long, value_name = "FILE", env = "EDMAN_RECORD")]
+    record: Option<PathBuf>,
+
     #[clap(flatten)]
     browser_arguments: BrowserArguments,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Send the requests of a recorded session to edman-main again and compare the replies
+    Replay {
+        /// File written with --record
+        file: PathBuf,
+
+        /// Connect to edman-main at this URL instead of the local socket, e.g. to a mock server
+        #[arg(long)]
+        endpoint: Option<String>,
+    },
 }
 
 #[derive(Args)]
 #[group(id = "browser")]
 struct BrowserArguments {
+    /// Origin of the extension from Chromium, path to the app manifest from Firefox
+    #[arg(hide = true)]
+    caller: Option<String>,
+
+    /// Extension ID from Firefox
     #[arg(hide = true)]
-    origin: Option<String>,
+    extension_id: Option<String>,
 
     #[arg(hide = true, long)]
     parent_window: Option<i32>,
@@ -58,40 +125,125 @@ async fn main_procedure() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::parse();
 
     if let Some(options) = cli.uninstall {
-        return installer::uninstall(options);
+        return installer::uninstall(options, cli.system, cli.dry_run);
+    }
+    if let Some(Command::Replay { file, endpoint }) = cli.command {
+        let channel = match endpoint {
+            Some(endpoint) => {
+                tonic::transport::Endpoint::from_shared(endpoint)?
+                    .connect()
+                    .await?
+            }
+            None => transport::connect_or_launch().await?,
+        };
+        return recording::replay(&file, DownloadManagerClient::new(channel)).await;
+    }
+
+    let is_native_messaging =
+        cli.install.is_none() && cli.manifest.is_none() && !cli.install_all && !cli.status;
+    let caller = Caller::from_args(
+        cli.browser_arguments.caller.as_deref(),
+        cli.browser_arguments.extension_id.as_deref(),
+    );
+    if is_native_messaging && caller.is_none() {
+        return Err("No caller identity. edman-ce-adapter has to be launched by a browser with the arguments of Chromium (origin) or Firefox (manifest path and extension ID).".into());
     }
 
-    let channel = transport::connect().await?;
+    let channel = transport::connect_or_launch().await?;
     let mut client = DownloadManagerClient::new(channel);
     let config_response = client
-        .get_config(tonic::Request::new(chrome_extension::ConfigRequest {}))
+        .get_config(tonic::Request::new(chrome_extension::ConfigRequest {
+            origin: caller.as_ref().map(|caller| caller.identity().to_owned()),
+        }))
         .await?;
     let config = config_response.get_ref().config.as_ref().unwrap();
+    let all_profiles = manifest::merge_profiles(&config_response.get_ref().profiles);
 
     if let Some(options) = cli.install {
-        let manifest = AppManifest::new(&options, config)?;
+        let manifest = AppManifest::new(&options, &all_profiles)?;
         let manifest_str = serde_json::to_string_pretty(&manifest)?;
-        installer::install(&options, manifest_str)?;
+        let installed = installer::install(&options, manifest_str, cli.system, cli.dry_run)?;
+        register_manifests(&mut client, &installed).await;
+    } else if cli.install_all {
+        let outcome = installer::install_all(&all_profiles, cli.system, cli.dry_run)?;
+        register_manifests(&mut client, &outcome.installed).await;
+        if !outcome.failed.is_empty() {
+            return Err(format!("Installation failed for {}.", outcome.failed.join(", ")).into());
+        }
+    } else if cli.status {
+        let statuses = installer::status(&all_profiles, cli.system)?;
+        installer::print_status(&statuses, cli.format)?;
     } else if let Some(options) = cli.manifest {
-        let manifest = AppManifest::new(&options, config)?;
+        let manifest = AppManifest::new(&options, &all_profiles)?;
         let manifest_str = serde_json::to_string_pretty(&manifest)?;
         println!("{}", manifest_str);
-    } else {
-        if let Some(ref origin) = cli.browser_arguments.origin {
-            if !config.allowed_origins.contains(origin) {
-                Err(anyhow::anyhow!("Origin \"{}\" is not allowed!", origin))?;
+    } else if let Some(caller) = caller {
+        // Requests from a caller that is not allowed are answered with an error
+        let session = Session {
+            config: config.to_owned(),
+            caller,
+        };
+
+        let recorder = match cli.record {
+            Some(ref path) => {
+                let caller_args = [
+                    cli.browser_arguments.caller,
+                    cli.browser_arguments.extension_id,
+                ];
+                Some(Recorder::create(
+                    path,
+                    caller_args.into_iter().flatten().collect(),
+                )?)
             }
-        }
+            None => None,
+        };
 
-        let stdin = std::io::stdin().lock();
-        let stdout = std::io::stdout().lock();
+        let stdin = tokio::io::stdin();
+        let stdout = tokio::io::stdout();
 
-        main_loop(&mut client, config, stdin, stdout).await?;
+        main_loop(
+            client,
+            Arc::new(session),
+            cli.max_in_flight,
+            stdin,
+            stdout,
+            recorder,
+        )
+        .await?;
     }
 
     Ok(())
 }
 
+/// Tells edman-main about installed manifests, so that it rewrites them when the
+/// allow-lists change.
+///
+/// The manifests work without it, so failures are only reported.
+async fn register_manifests(
+    client: &mut DownloadManagerClient<tonic::transport::Channel>,
+    installed: &[installer::Installed],
+) {
+    for entry in installed {
+        let kind: chrome_extension::ManifestKind = BrowserStrain::from(&entry.browser).into();
+        let result = client
+            .register_manifest(tonic::Request::new(
+                chrome_extension::RegisterManifestRequest {
+                    browser: entry.browser.name().to_string(),
+                    path: entry.manifest_file.to_string_lossy().into_owned(),
+                    kind: kind as i32,
+                },
+            ))
+            .await;
+        if let Err(err) = result {
+            eprintln!(
+                "edman-main will not update the manifest at {} when the allow-lists change: {}",
+                entry.manifest_file.display(),
+                err.message()
+            );
+        }
+    }
+}
+
 fn write_error_log(err: Box<dyn std::error::Error>) -> i32 {
     use std::{
         fs::OpenOptions,
@@ -1,29 +1,15 @@
 use std::path::PathBuf;
 
-use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
+pub use utils::browsers::{BrowserKind, BrowserStrain};
 
-use crate::config;
+use crate::{chrome_extension, config};
 
-#[derive(Clone, Copy, ValueEnum)]
-pub enum BrowserKind {
-    Chrome,
-    Chromium,
-    Vivaldi,
-    Firefox,
-}
-
-#[derive(Clone, Copy)]
-pub enum BrowserStrain {
-    Chromium,
-    Firefox,
-}
-
-impl From<&BrowserKind> for BrowserStrain {
-    fn from(value: &BrowserKind) -> Self {
+impl From<BrowserStrain> for chrome_extension::ManifestKind {
+    fn from(value: BrowserStrain) -> Self {
         match value {
-            BrowserKind::Firefox => Self::Firefox,
-            _ => Self::Chromium,
+            BrowserStrain::Chromium => Self::Chromium,
+            BrowserStrain::Firefox => Self::Firefox,
         }
     }
 }
@@ -70,3 +56,20 @@ impl AppManifest {
         })
     }
 }
+
+/// Combines the allow-lists of all profiles, so that any of them can launch the host.
+pub fn merge_profiles(profiles: &[config::Profile]) -> config::Config {
+    let configs: Vec<_> = profiles
+        .iter()
+        .filter_map(|profile| profile.config.as_ref())
+        .collect();
+    config::Config {
+        allowed_origins: utils::browsers::merge_allow_lists(
+            configs.iter().map(|config| &config.allowed_origins[..]),
+        ),
+        allowed_extensions: utils::browsers::merge_allow_lists(
+            configs.iter().map(|config| &config.allowed_extensions[..]),
+        ),
+        ..Default::default()
+    }
+}
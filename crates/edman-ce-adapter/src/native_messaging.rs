@@ -1,26 +1,66 @@
-use std::{
-    io::{Read, Write},
-    path::PathBuf,
-};
+use std::sync::Arc;
 
+use crate::caller::Caller;
 use crate::chrome_extension;
 use crate::chrome_extension::download_manager_client::DownloadManagerClient;
+use crate::codec::NativeCodec;
 use crate::config;
-use byteorder::{NativeEndian, ReadBytesExt, WriteBytesExt};
+use crate::error::{NativeError, NativeErrorCode};
+use crate::recording::Recorder;
+use futures_util::SinkExt;
 use serde::{Deserialize, Serialize};
+use strum::VariantNames;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{mpsc, Semaphore};
+use tokio_stream::StreamExt;
+use tokio_util::codec::{FramedRead, FramedWrite};
+
+/// Chrome drops messages from a native host that are larger than this
+const MAX_OUTGOING_SIZE: usize = 1024 * 1024;
+/// Room left in each chunk for the `id` and the other fields around `payload`
+const CHUNK_OVERHEAD: usize = 1024;
+
+/// Oldest protocol version of the extension that this host still understands
+const MIN_PROTOCOL_VERSION: u32 = 1;
+/// `type`s of the messages this host understands
+const MESSAGE_KINDS: &[&str] = NativeMessageKinds::VARIANTS;
+/// Optional behaviours this host supports
+const FEATURES: &[&str] = &["chunked_replies"];
+
+/// State shared by every message of one native messaging connection
+pub struct Session {
+    pub config: config::Config,
+    /// Browser extension that launched this process
+    pub caller: Caller,
+}
+
+impl Session {
+    /// Identity of the caller, sent along so that edman-main picks its profile
+    fn origin(&self) -> Option<String> {
+        Some(self.caller.identity().to_owned())
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 struct NativeMessage {
     id: Option<String>,
 
+    /// Parsed separately, so that a message this host does not know gets an error reply
     #[serde(flatten)]
-    message: NativeMessageKinds,
+    message: serde_json::Value,
 }
 
 #[typeshare::typeshare]
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, strum::EnumVariantNames)]
 #[serde(tag = "type", content = "data", rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
 enum NativeMessageKinds {
+    /// Sent by the extension before anything else
+    #[serde(rename_all = "camelCase")]
+    Hello {
+        protocol_version: u32,
+        features: Vec<String>,
+    },
     Config,
     #[serde(rename_all = "camelCase")]
     FetchFileStates {
@@ -31,10 +71,116 @@ enum NativeMessageKinds {
         download_path: String,
         save_path: Vec<String>,
         key: String,
+        /// Page the file was downloaded from, which origin quotas apply to
+        page_origin: Option<String>,
+    },
+    /// Several finished downloads at once, registered in one transaction
+    RegisterFiles {
+        files: Vec<FileToRegister>,
+    },
+    /// Lets edman fetch `url` itself instead of going through the browser's downloads
+    #[serde(rename_all = "camelCase")]
+    EnqueueDownload {
+        url: String,
+        save_path: Vec<String>,
+        key: String,
+        /// `Cookie` header for `url`
+        cookie: Option<String>,
+        /// Hex encoded SHA-256 the content has to match
+        sha256: Option<String>,
+        /// Jobs with a higher priority start first
+        priority: Option<i32>,
+        /// Page that links to `url`, which origin quotas apply to
+        page_origin: Option<String>,
+    },
+    /// Tells edman about a download the browser is fetching
+    ReportProgress(DownloadProgress),
+}
+
+/// One file of a `register_files` message, same as the data of `register_file`
+#[typeshare::typeshare]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+struct FileToRegister {
+    download_path: String,
+    save_path: Vec<String>,
+    key: String,
+    /// Page the file was downloaded from, which origin quotas apply to
+    page_origin: Option<String>,
+}
+
+/// Result of one file of a `register_files` message
+#[typeshare::typeshare]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+enum RegisteredFile {
+    /// Same as the reply to `register_file`
+    #[serde(rename_all = "camelCase")]
+    Ok {
+        id: Option<i32>,
+        path: String,
     },
+    Err(NativeError),
+}
+
+impl From<chrome_extension::RegisterFilesResult> for RegisteredFile {
+    fn from(result: chrome_extension::RegisterFilesResult) -> Self {
+        match (result.file, result.error) {
+            (Some(file), _) => Self::Ok {
+                id: file.id,
+                path: file.path,
+            },
+            (None, Some(error)) => {
+                Self::Err(tonic::Status::new(tonic::Code::from(error.code), error.message).into())
+            }
+            (None, None) => Self::Err(NativeError::new(
+                NativeErrorCode::Internal,
+                "edman-main sent no result for this file",
+            )),
+        }
+    }
+}
+
+/// State of a browser download, as in `chrome.downloads.State`
+#[typeshare::typeshare]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum DownloadState {
+    InProgress,
+    Interrupted,
+    Complete,
+}
+
+impl From<DownloadState> for chrome_extension::ProgressState {
+    fn from(state: DownloadState) -> Self {
+        match state {
+            DownloadState::InProgress => Self::InProgress,
+            DownloadState::Interrupted => Self::Interrupted,
+            DownloadState::Complete => Self::Complete,
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[typeshare::typeshare]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+struct DownloadProgress {
+    /// Id the browser assigned to the download
+    download_id: i32,
+    url: String,
+    /// Absolute path the browser writes to
+    filename: String,
+    #[typeshare(serialized_as = "number")]
+    bytes_received: u64,
+    /// 0 if the size is unknown
+    #[typeshare(serialized_as = "number")]
+    total_bytes: u64,
+    state: DownloadState,
+    /// Why the download was interrupted
+    error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 struct NativeResult {
     id: Option<String>,
 
@@ -43,9 +189,21 @@ struct NativeResult {
 }
 
 #[typeshare::typeshare]
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 #[serde(tag = "type", content = "data", rename_all = "snake_case")]
 enum NativeResultKinds {
+    #[serde(rename_all = "camelCase")]
+    Hello {
+        protocol_version: u32,
+        /// Version of edman-ce-adapter
+        adapter_version: String,
+        /// Version of edman-main
+        server_version: String,
+        /// `type`s of the messages the host understands
+        message_kinds: Vec<String>,
+        /// Features requested by the extension that the host supports
+        features: Vec<String>,
+    },
     #[serde(rename_all = "camelCase")]
     Config {
         download_subdirectory: String,
@@ -53,48 +211,262 @@ enum NativeResultKinds {
     #[serde(rename_all = "camelCase")]
     FetchFileStates(chrome_extension::GetFileStatesReply),
     #[serde(rename_all = "camelCase")]
-    RegisterFile(chrome_extension::RegisterFileReply),
-    Err(String),
+    RegisterFile {
+        /// `None` if the file was skipped because of a collision
+        id: Option<i32>,
+        /// Path the file was actually saved to, relative to the save directory
+        path: String,
+    },
+    /// One result for each file, in the order of the request
+    RegisterFiles {
+        results: Vec<RegisteredFile>,
+    },
+    /// The download has been queued and continues in the background
+    #[serde(rename_all = "camelCase")]
+    EnqueueDownload {
+        job_id: i32,
+    },
+    ReportProgress,
+    /// One part of a reply that does not fit into a single message.
+    ///
+    /// Replies longer than 1 MB are sent as `count` chunks with the id of the
+    /// request, in order of `index`. Concatenating their `payload`s gives the
+    /// JSON of the complete reply.
+    #[serde(rename_all = "camelCase")]
+    Chunk {
+        index: u32,
+        count: u32,
+        payload: String,
+    },
+    Err(NativeError),
 }
 
+/// Reads requests from `stdin` and answers them on `stdout`, writing both to
+/// `recorder` if given.
+///
+/// Up to `max_in_flight` requests are processed at the same time, so replies
+/// may be written in a different order than the requests arrived. The browser
+/// matches them up by `id`.
 pub async fn main_loop(
-    client: &mut DownloadManagerClient<tonic::transport::Channel>,
-    config: &config::Config,
-    mut stdin: impl Read,
-    mut stdout: impl Write,
+    client: DownloadManagerClient<tonic::transport::Channel>,
+    session: Arc<Session>,
+    max_in_flight: usize,
+    stdin: impl AsyncRead + Unpin,
+    stdout: impl AsyncWrite + Unpin + Send + 'static,
+    recorder: Option<Arc<Recorder>>,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    while let Ok(size) = stdin.read_u32::<NativeEndian>() {
-        let mut input_buf = vec![0u8; size as usize];
-        stdin.read_exact(&mut input_buf)?;
-        let input_str = String::from_utf8(input_buf)?;
-
-        let NativeMessage { id, message } = serde_json::from_str(&input_str)?;
+    let codec = NativeCodec::<NativeMessage, NativeResult>::default;
+    let mut messages = FramedRead::new(stdin, codec());
+    let results = FramedWrite::new(stdout, codec());
 
-        let reply_message = get_reply(client, config, message)
-            .await
-            .unwrap_or_else(|err| NativeResultKinds::Err(err.to_string()));
+    let (reply_tx, reply_rx) = mpsc::channel(max_in_flight.max(1));
+    let writer = tokio::spawn(write_results(reply_rx, results, recorder.clone()));
+    let in_flight = Arc::new(Semaphore::new(max_in_flight.max(1)));
 
-        let native_result = NativeResult {
-            id,
-            message: reply_message,
+    // Ends when the browser closes stdin. A truncated or malformed frame is an error,
+    // which is returned once the requests read before it have been answered.
+    let mut read_result = Ok(());
+    while let Some(native_message) = messages.next().await {
+        let native_message = match native_message {
+            Ok(native_message) => native_message,
+            Err(err) => {
+                read_result = Err(err);
+                break;
+            }
         };
+        if let Some(ref recorder) = recorder {
+            recorder.record_in(&native_message);
+        }
+        let NativeMessage { id, message } = native_message;
 
-        let output_str = serde_json::to_string(&native_result)?;
+        let permit = in_flight.clone().acquire_owned().await?;
+        let mut client = client.clone();
+        let session = session.clone();
+        let reply_tx = reply_tx.clone();
+        tokio::spawn(async move {
+            let reply_message = match parse_message(message) {
+                Ok(message) => get_reply(&mut client, &session, message).await,
+                Err(err) => Err(err),
+            }
+            .unwrap_or_else(NativeResultKinds::Err);
 
-        stdout.write_u32::<NativeEndian>(output_str.len() as u32)?;
-        stdout.write_all(output_str.as_bytes())?;
-        stdout.flush()?;
+            let native_result = NativeResult {
+                id,
+                message: reply_message,
+            };
+            // The writer only stops early if stdout is gone, which is reported below
+            let _ = reply_tx.send(native_result).await;
+            drop(permit);
+        });
     }
 
+    // Every permit is back once the pending requests have sent their replies
+    let _ = in_flight.acquire_many(max_in_flight.max(1) as u32).await;
+    drop(reply_tx);
+    let write_result = writer.await;
+
+    read_result?;
+    write_result??;
     Ok(())
 }
 
+async fn write_results(
+    mut reply_rx: mpsc::Receiver<NativeResult>,
+    mut results: FramedWrite<impl AsyncWrite + Unpin, NativeCodec<NativeMessage, NativeResult>>,
+    recorder: Option<Arc<Recorder>>,
+) -> std::io::Result<()> {
+    while let Some(native_result) = reply_rx.recv().await {
+        for part in split_result(native_result)? {
+            if let Some(ref recorder) = recorder {
+                recorder.record_out(&part);
+            }
+            results.feed(part).await?;
+        }
+        results.flush().await?;
+    }
+
+    Ok(())
+}
+
+/// Splits `result` into chunks if it is too large for a single message.
+fn split_result(result: NativeResult) -> Result<Vec<NativeResult>, serde_json::Error> {
+    let output_str = serde_json::to_string(&result)?;
+    if output_str.len() <= MAX_OUTGOING_SIZE {
+        return Ok(vec![result]);
+    }
+
+    let payloads = split_payload(&output_str, MAX_OUTGOING_SIZE - CHUNK_OVERHEAD);
+    let count = payloads.len() as u32;
+    Ok(payloads
+        .into_iter()
+        .enumerate()
+        .map(|(index, payload)| NativeResult {
+            id: result.id.to_owned(),
+            message: NativeResultKinds::Chunk {
+                index: index as u32,
+                count,
+                payload: payload.to_owned(),
+            },
+        })
+        .collect())
+}
+
+/// Splits `s` at char boundaries so that each part stays within `limit` bytes once escaped as a JSON string.
+fn split_payload(s: &str, limit: usize) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut escaped_len = 0;
+    for (pos, c) in s.char_indices() {
+        // `s` is JSON itself, so quotes and backslashes are the only characters that get escaped
+        let len = match c {
+            '"' | '\\' => 2,
+            c => c.len_utf8(),
+        };
+        if escaped_len + len > limit {
+            parts.push(&s[start..pos]);
+            start = pos;
+            escaped_len = 0;
+        }
+        escaped_len += len;
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+/// Rejects callers that are not in the allow-lists of the profile of the session.
+fn check_origin(session: &Session) -> Result<(), NativeError> {
+    if session.caller.is_allowed(&session.config) {
+        return Ok(());
+    }
+
+    Err(NativeError::new(
+        NativeErrorCode::OriginNotAllowed,
+        format!("\"{}\" is not allowed!", session.caller.identity()),
+    ))
+}
+
+/// Parses a message, explaining the failure if it comes from a newer or older extension.
+fn parse_message(message: serde_json::Value) -> Result<NativeMessageKinds, NativeError> {
+    let kind = message
+        .get("type")
+        .and_then(|kind| kind.as_str())
+        .unwrap_or_default()
+        .to_owned();
+    if !MESSAGE_KINDS.contains(&&kind[..]) {
+        return Err(NativeError::new(
+            NativeErrorCode::UnknownMessage,
+            format!(
+                "Unknown message type \"{}\". edman-ce-adapter {} only understands {}; the extension and edman are probably out of date with each other.",
+                kind,
+                env!("CARGO_PKG_VERSION"),
+                MESSAGE_KINDS.join(", ")
+            ),
+        ));
+    }
+
+    serde_json::from_value(message).map_err(|err| NativeError {
+        details: Some(err.to_string()),
+        ..NativeError::new(
+            NativeErrorCode::InvalidMessage,
+            format!(
+                "Invalid \"{}\" message for edman-ce-adapter {}",
+                kind,
+                env!("CARGO_PKG_VERSION")
+            ),
+        )
+    })
+}
+
 async fn get_reply(
     client: &mut DownloadManagerClient<tonic::transport::Channel>,
-    config: &config::Config,
+    session: &Session,
     native_message: NativeMessageKinds,
-) -> Result<NativeResultKinds, Box<dyn std::error::Error>> {
+) -> Result<NativeResultKinds, NativeError> {
+    let config = &session.config;
+    // The handshake is allowed, so that the extension learns why everything else fails
+    if !matches!(native_message, NativeMessageKinds::Hello { .. }) {
+        check_origin(session)?;
+    }
+
     let reply_message = match native_message {
+        NativeMessageKinds::Hello {
+            protocol_version,
+            features,
+        } => {
+            if !(MIN_PROTOCOL_VERSION..=utils::NATIVE_PROTOCOL_VERSION).contains(&protocol_version)
+            {
+                return Err(NativeError::new(
+                    NativeErrorCode::ProtocolMismatch,
+                    format!(
+                        "The extension uses protocol version {}, but edman-ce-adapter {} supports versions {} to {}. Update {}.",
+                        protocol_version,
+                        env!("CARGO_PKG_VERSION"),
+                        MIN_PROTOCOL_VERSION,
+                        utils::NATIVE_PROTOCOL_VERSION,
+                        if protocol_version > utils::NATIVE_PROTOCOL_VERSION {
+                            "edman"
+                        } else {
+                            "the extension"
+                        }
+                    ),
+                ));
+            }
+
+            let server_info = client
+                .get_server_info(tonic::Request::new(chrome_extension::ServerInfoRequest {}))
+                .await?
+                .into_inner();
+            NativeResultKinds::Hello {
+                protocol_version: utils::NATIVE_PROTOCOL_VERSION,
+                adapter_version: env!("CARGO_PKG_VERSION").to_string(),
+                server_version: server_info.version,
+                message_kinds: MESSAGE_KINDS.iter().map(|kind| kind.to_string()).collect(),
+                features: features
+                    .into_iter()
+                    .filter(|feature| FEATURES.contains(&&feature[..]))
+                    .collect(),
+            }
+        }
         NativeMessageKinds::Config => NativeResultKinds::Config {
             download_subdirectory: config.download_subdirectory.to_owned(),
         },
@@ -107,32 +479,89 @@ async fn get_reply(
             download_path,
             save_path,
             key,
+            page_origin,
         } => {
-            let downloaded_file_path =
-                PathBuf::from(&config.download_directory).join(&download_path);
-
-            if save_path
-                .iter()
-                .any(|p| p.contains(['/', '\\']) || p.contains(".."))
-            {
-                let err = anyhow::anyhow!("savePath must not contain slashes or dots.");
-                Err(err)?;
+            let request = chrome_extension::CommitDownloadRequest {
+                download_path,
+                save_path,
+                key,
+                origin: session.origin(),
+                page_origin,
+            };
+            let response = client
+                .commit_download(tonic::Request::new(request))
+                .await?
+                .into_inner();
+            NativeResultKinds::RegisterFile {
+                id: response.id,
+                path: response.path,
             }
-            let save_path_str = save_path.join("/");
-
-            let save_dir = PathBuf::from(&config.save_file_directory)
-                .join(save_path[..save_path.len() - 1].join("/"));
-            let save_file_path = PathBuf::from(&config.save_file_directory).join(&save_path_str);
-
-            std::fs::create_dir_all(save_dir)?;
-            std::fs::rename(downloaded_file_path, save_file_path)?;
-
-            let request = chrome_extension::RegisterFileRequest {
-                path: save_path_str,
+        }
+        NativeMessageKinds::RegisterFiles { files } => {
+            let request = chrome_extension::RegisterFilesRequest {
+                files: files
+                    .into_iter()
+                    .map(|file| chrome_extension::RegisterFilesItem {
+                        download_path: file.download_path,
+                        save_path: file.save_path,
+                        key: file.key,
+                        page_origin: file.page_origin,
+                    })
+                    .collect(),
+                origin: session.origin(),
+            };
+            let reply = client
+                .register_files(tonic::Request::new(request))
+                .await?
+                .into_inner();
+            NativeResultKinds::RegisterFiles {
+                results: reply
+                    .results
+                    .into_iter()
+                    .map(RegisteredFile::from)
+                    .collect(),
+            }
+        }
+        NativeMessageKinds::EnqueueDownload {
+            url,
+            save_path,
+            key,
+            cookie,
+            sha256,
+            priority,
+            page_origin,
+        } => {
+            let request = chrome_extension::EnqueueDownloadRequest {
+                url,
+                save_path,
                 key,
+                origin: session.origin(),
+                cookie,
+                sha256,
+                priority: priority.unwrap_or_default(),
+                page_origin,
+            };
+            let reply = client
+                .enqueue_download(tonic::Request::new(request))
+                .await?
+                .into_inner();
+            NativeResultKinds::EnqueueDownload {
+                job_id: reply.job_id,
+            }
+        }
+        NativeMessageKinds::ReportProgress(progress) => {
+            let request = chrome_extension::ReportProgressRequest {
+                download_id: progress.download_id,
+                url: progress.url,
+                filename: progress.filename,
+                origin: session.origin(),
+                bytes_received: progress.bytes_received,
+                total_bytes: progress.total_bytes,
+                state: chrome_extension::ProgressState::from(progress.state) as i32,
+                error: progress.error,
             };
-            let response = client.register_file(tonic::Request::new(request)).await?;
-            NativeResultKinds::RegisterFile(response.get_ref().to_owned())
+            client.report_progress(tonic::Request::new(request)).await?;
+            NativeResultKinds::ReportProgress
         }
     };
 
@@ -141,24 +570,45 @@ async fn get_reply(
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
+    use tokio_stream::StreamExt;
+    use tokio_util::codec::FramedRead;
+
+    use crate::caller::Caller;
     use crate::chrome_extension::{
         self, download_manager_client::DownloadManagerClient, GetFileStatesReply,
     };
-    use crate::native_messaging::{get_reply, NativeMessageKinds, NativeResultKinds};
+    use crate::codec::NativeCodec;
+    use crate::error::NativeErrorCode;
+    use crate::native_messaging::{
+        get_reply, main_loop, parse_message, split_result, DownloadProgress, DownloadState,
+        NativeMessage, NativeMessageKinds, NativeResult, NativeResultKinds, RegisteredFile,
+        Session, MAX_OUTGOING_SIZE, MESSAGE_KINDS,
+    };
 
     #[tokio::test]
     async fn test_file_states() -> Result<(), Box<dyn std::error::Error>> {
         let input_str = "{\"type\":\"fetch_file_states\",\"data\":{\"query\":[]}}";
 
-        let mut client = DownloadManagerClient::connect("http://[::1]:50051").await?;
+        let harness = edman_main::harness::Harness::start().await?;
+        let mut client = DownloadManagerClient::new(harness.channel());
 
         let config_response = client
-            .get_config(tonic::Request::new(chrome_extension::ConfigRequest {}))
+            .get_config(tonic::Request::new(chrome_extension::ConfigRequest {
+                origin: None,
+            }))
             .await?;
-        let config = config_response.get_ref();
+        let origin = "chrome-extension://test/".to_string();
+        let mut config = config_response.into_inner().config.unwrap();
+        config.allowed_origins.push(origin.to_owned());
+        let session = Session {
+            config,
+            caller: Caller::Chromium { origin },
+        };
         let native_message: NativeMessageKinds = serde_json::from_str(input_str)?;
 
-        let reply = get_reply(&mut client, config.config.as_ref().unwrap(), native_message).await?;
+        let reply = get_reply(&mut client, &session, native_message).await?;
 
         assert_eq!(
             reply,
@@ -168,6 +618,45 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn malformed_frame_after_a_request() {
+        let mut stdin = vec![];
+        for frame in [r#"{"id":"1","type":"teleport","data":{}}"#, "not json"] {
+            stdin.extend_from_slice(&(frame.len() as u32).to_ne_bytes());
+            stdin.extend_from_slice(frame.as_bytes());
+        }
+        let (stdout, replies) = tokio::io::duplex(64 * 1024);
+        // Never connects, since the request is answered without edman-main
+        let channel = tonic::transport::Endpoint::from_static("http://[::1]:1").connect_lazy();
+        let session = Arc::new(Session {
+            config: Default::default(),
+            caller: Caller::Chromium {
+                origin: "chrome-extension://test/".to_string(),
+            },
+        });
+
+        let result = main_loop(
+            DownloadManagerClient::new(channel),
+            session,
+            4,
+            &stdin[..],
+            stdout,
+            None,
+        )
+        .await;
+        assert!(result.is_err());
+
+        // The request before the malformed frame is still answered
+        let mut replies = FramedRead::new(
+            replies,
+            NativeCodec::<NativeResult, NativeMessage>::default(),
+        );
+        let reply = replies.next().await.unwrap().unwrap();
+        assert_eq!(reply.id.as_deref(), Some("1"));
+        assert!(matches!(reply.message, NativeResultKinds::Err(_)));
+        assert!(replies.next().await.is_none());
+    }
+
     #[test]
     fn parse_register() {
         let input_str = "{\"type\":\"register_file\",\"data\":{\"downloadPath\":\"a\",\"savePath\":[\"b\",\"d\"],\"key\":\"c\"}}";
@@ -178,8 +667,121 @@ mod tests {
             NativeMessageKinds::RegisterFile {
                 download_path: "a".to_string(),
                 save_path: vec!["b".to_string(), "d".to_string()],
-                key: "c".to_string()
+                key: "c".to_string(),
+                page_origin: None,
             }
         );
     }
+
+    #[test]
+    fn file_error_keeps_its_code() {
+        let result = chrome_extension::RegisterFilesResult {
+            file: None,
+            error: Some(chrome_extension::FileError {
+                code: tonic::Code::ResourceExhausted as i32,
+                message: "Quota exceeded".to_string(),
+            }),
+        };
+
+        let RegisteredFile::Err(err) = RegisteredFile::from(result) else {
+            panic!("Expected an error");
+        };
+        assert_eq!(err.code, NativeErrorCode::QuotaExceeded);
+        assert_eq!(err.message, "Quota exceeded");
+    }
+
+    #[test]
+    fn chunk_large_reply() -> Result<(), Box<dyn std::error::Error>> {
+        let result = NativeResult {
+            id: Some("1".to_string()),
+            message: NativeResultKinds::FetchFileStates(GetFileStatesReply {
+                result: vec![true; MAX_OUTGOING_SIZE / 2],
+            }),
+        };
+
+        let chunks = split_result(result.clone())?;
+        let count_total = chunks.len();
+        assert!(count_total > 1);
+
+        let mut payload = String::new();
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            assert!(serde_json::to_string(&chunk)?.len() <= MAX_OUTGOING_SIZE);
+            assert_eq!(chunk.id, result.id);
+            let NativeResultKinds::Chunk {
+                index,
+                count,
+                payload: part,
+            } = chunk.message
+            else {
+                panic!("Expected a chunk, got {:?}", chunk.message);
+            };
+            assert_eq!(index as usize, i);
+            assert_eq!(count as usize, count_total);
+            payload.push_str(&part);
+        }
+
+        assert_eq!(serde_json::from_str::<NativeResult>(&payload)?, result);
+
+        Ok(())
+    }
+
+    #[test]
+    fn message_kinds_are_serde_types() {
+        let messages = [
+            NativeMessageKinds::Hello {
+                protocol_version: 1,
+                features: vec![],
+            },
+            NativeMessageKinds::Config,
+            NativeMessageKinds::FetchFileStates { query: vec![] },
+            NativeMessageKinds::RegisterFile {
+                download_path: "edman/a.txt".to_string(),
+                save_path: vec!["a.txt".to_string()],
+                key: "a".to_string(),
+                page_origin: None,
+            },
+            NativeMessageKinds::RegisterFiles { files: vec![] },
+            NativeMessageKinds::EnqueueDownload {
+                url: "https://example.com/a.txt".to_string(),
+                save_path: vec!["a.txt".to_string()],
+                key: "a".to_string(),
+                cookie: None,
+                sha256: None,
+                priority: None,
+                page_origin: None,
+            },
+            NativeMessageKinds::ReportProgress(DownloadProgress {
+                download_id: 1,
+                url: "https://example.com/a.txt".to_string(),
+                filename: "/tmp/a.txt".to_string(),
+                bytes_received: 0,
+                total_bytes: 0,
+                state: DownloadState::InProgress,
+                error: None,
+            }),
+        ];
+        let mut types: Vec<_> = messages
+            .iter()
+            .map(|message| {
+                let value = serde_json::to_value(message).unwrap();
+                value["type"].as_str().unwrap().to_owned()
+            })
+            .collect();
+        types.sort_unstable();
+
+        let mut kinds = MESSAGE_KINDS.to_vec();
+        kinds.sort_unstable();
+        // Every kind is listed above, so a variant that serde names differently fails here
+        assert_eq!(types, kinds);
+    }
+
+    #[test]
+    fn unknown_message_type() {
+        let message = serde_json::json!({ "type": "teleport", "data": {} });
+        let err = parse_message(message).unwrap_err();
+
+        assert_eq!(err.code, NativeErrorCode::UnknownMessage);
+        assert!(err.message.contains("Unknown message type \"teleport\""));
+        assert!(err.message.contains("register_file"));
+    }
 }
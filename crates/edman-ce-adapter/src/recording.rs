@@ -0,0 +1,367 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::BytesMut;
+use serde::{Deserialize, Serialize};
+use tokio_stream::StreamExt;
+use tokio_util::codec::{Encoder, FramedRead};
+
+use crate::caller::Caller;
+use crate::chrome_extension::{self, download_manager_client::DownloadManagerClient};
+use crate::codec::NativeCodec;
+use crate::native_messaging::{main_loop, Session};
+
+/// One line of a recording.
+///
+/// Several hosts may record into the same file at once, so every line carries the
+/// process id of the host that wrote it.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Entry {
+    /// Written when a host starts recording
+    Start {
+        pid: u32,
+        time_ms: u64,
+        adapter_version: String,
+        /// Positional arguments the browser launched the host with
+        caller: Vec<String>,
+    },
+    /// Frame received from the browser
+    In {
+        pid: u32,
+        time_ms: u64,
+        frame: serde_json::Value,
+    },
+    /// Frame sent to the browser
+    Out {
+        pid: u32,
+        time_ms: u64,
+        frame: serde_json::Value,
+    },
+}
+
+/// Fields that carry credentials, compared case-insensitively at any depth of a frame.
+///
+/// Their values are replaced by [`REDACTED`] before a frame is recorded, so that a
+/// recording can be shared in a bug report.
+const CREDENTIAL_FIELDS: &[&str] = &["cookie", "authorization", "token", "password"];
+
+/// Value of a credential field in a recording
+const REDACTED: &str = "[redacted]";
+
+/// Appends every native frame of a session to a file, one JSON object per line
+pub struct Recorder {
+    file: Mutex<std::fs::File>,
+}
+
+impl Recorder {
+    /// Starts a session in the file at `path` with the arguments of the caller.
+    pub fn create(path: &Path, caller_args: Vec<String>) -> std::io::Result<Arc<Self>> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        let recorder = Self {
+            file: Mutex::new(file),
+        };
+        recorder.write(&Entry::Start {
+            pid: std::process::id(),
+            time_ms: now_ms(),
+            adapter_version: env!("CARGO_PKG_VERSION").to_string(),
+            caller: caller_args,
+        })?;
+        Ok(Arc::new(recorder))
+    }
+
+    pub fn record_in(&self, frame: &impl Serialize) {
+        self.record(frame, |pid, time_ms, frame| Entry::In {
+            pid,
+            time_ms,
+            frame,
+        });
+    }
+
+    pub fn record_out(&self, frame: &impl Serialize) {
+        self.record(frame, |pid, time_ms, frame| Entry::Out {
+            pid,
+            time_ms,
+            frame,
+        });
+    }
+
+    /// A recording that cannot be written must not break the session, so errors are only printed.
+    fn record(&self, frame: &impl Serialize, entry: fn(u32, u64, serde_json::Value) -> Entry) {
+        let result = serde_json::to_value(frame)
+            .map_err(std::io::Error::from)
+            .and_then(|mut frame| {
+                redact(&mut frame);
+                self.write(&entry(std::process::id(), now_ms(), frame))
+            });
+        if let Err(err) = result {
+            eprintln!("Cannot record a native message: {}", err);
+        }
+    }
+
+    fn write(&self, entry: &Entry) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        // One write per line, so that lines of concurrent hosts do not interleave
+        self.file.lock().unwrap().write_all(&line)
+    }
+}
+
+/// Replaces the values of the [`CREDENTIAL_FIELDS`] in `value`.
+fn redact(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (key, field) in fields.iter_mut() {
+                let is_credential = CREDENTIAL_FIELDS
+                    .iter()
+                    .any(|name| key.eq_ignore_ascii_case(name));
+                if is_credential {
+                    *field = serde_json::Value::String(REDACTED.to_string());
+                } else {
+                    redact(field);
+                }
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64)
+}
+
+/// Reply of the recording and of the replay that differ for one request id
+#[derive(Debug, PartialEq)]
+pub struct ReplyDiff {
+    pub id: Option<String>,
+    pub recorded: Vec<serde_json::Value>,
+    pub replayed: Vec<serde_json::Value>,
+}
+
+/// Frames of one host process in a recording
+struct RecordedSession {
+    caller_args: Vec<String>,
+    input: Vec<serde_json::Value>,
+    output: Vec<serde_json::Value>,
+}
+
+/// Feeds the frames that the browser sent in the recording at `path` into `main_loop`
+/// and compares the replies with the recorded ones.
+///
+/// Sessions are replayed one after another, and frames as fast as they are processed,
+/// without the recorded delays. Credentials are replayed as [`REDACTED`]. Returns an error
+/// if any reply differs.
+pub async fn replay(
+    path: &Path,
+    client: DownloadManagerClient<tonic::transport::Channel>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let sessions = read_sessions(path)?;
+    if sessions.is_empty() {
+        return Err(format!("{} does not contain any session.", path.display()).into());
+    }
+
+    let mut differing = 0;
+    for (i, session) in sessions.iter().enumerate() {
+        let replayed = replay_session(session, client.clone()).await?;
+        let diffs = diff_replies(&session.output, &replayed);
+        println!(
+            "Session {} ({}): {} of {} replies differ",
+            i + 1,
+            session.caller_args.join(" "),
+            diffs.len(),
+            replayed.len()
+        );
+        for diff in &diffs {
+            println!("  id {}", diff.id.as_deref().unwrap_or("(none)"));
+            println!("    recorded: {}", serde_json::to_string(&diff.recorded)?);
+            println!("    replayed: {}", serde_json::to_string(&diff.replayed)?);
+        }
+        differing += diffs.len();
+    }
+
+    if differing > 0 {
+        return Err(format!("{} replies differ from the recording.", differing).into());
+    }
+    Ok(())
+}
+
+async fn replay_session(
+    recorded: &RecordedSession,
+    mut client: DownloadManagerClient<tonic::transport::Channel>,
+) -> Result<Vec<serde_json::Value>, Box<dyn std::error::Error>> {
+    let caller = Caller::from_args(
+        recorded.caller_args.first().map(|arg| &arg[..]),
+        recorded.caller_args.get(1).map(|arg| &arg[..]),
+    )
+    .ok_or("The recorded arguments do not belong to any browser.")?;
+
+    let mut input = BytesMut::new();
+    for frame in &recorded.input {
+        NativeCodec::<(), serde_json::Value>::default().encode(frame.to_owned(), &mut input)?;
+    }
+
+    let config = client
+        .get_config(tonic::Request::new(chrome_extension::ConfigRequest {
+            origin: Some(caller.identity().to_owned()),
+        }))
+        .await?
+        .into_inner()
+        .config
+        .unwrap_or_default();
+    let session = Arc::new(Session { config, caller });
+
+    let (output, stdout) = tokio::io::duplex(64 * 1024);
+    let reader = tokio::spawn(async move {
+        FramedRead::new(output, NativeCodec::<serde_json::Value, ()>::default())
+            .collect::<Result<Vec<_>, _>>()
+            .await
+    });
+    // One at a time, so that requests reach edman-main in the recorded order
+    main_loop(client, session, 1, &input[..], stdout, None).await?;
+    Ok(reader.await??)
+}
+
+/// Splits the recording into the sessions of the hosts that wrote it, in order of their start.
+fn read_sessions(path: &Path) -> Result<Vec<RecordedSession>, Box<dyn std::error::Error>> {
+    let content = std::fs::read_to_string(path)?;
+
+    let mut sessions = vec![];
+    // Index into `sessions` of the latest session of each process id
+    let mut by_pid = HashMap::new();
+    for (i, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: Entry = serde_json::from_str(line)
+            .map_err(|err| format!("Line {} of {}: {}", i + 1, path.display(), err))?;
+
+        let (pid, frame, is_input) = match entry {
+            Entry::Start { pid, caller, .. } => {
+                by_pid.insert(pid, sessions.len());
+                sessions.push(RecordedSession {
+                    caller_args: caller,
+                    input: vec![],
+                    output: vec![],
+                });
+                continue;
+            }
+            Entry::In { pid, frame, .. } => (pid, frame, true),
+            Entry::Out { pid, frame, .. } => (pid, frame, false),
+        };
+        let session = by_pid
+            .get(&pid)
+            .map(|&index| &mut sessions[index])
+            .ok_or_else(|| {
+                format!(
+                    "Line {} of {}: no session started for process {}",
+                    i + 1,
+                    path.display(),
+                    pid
+                )
+            })?;
+        if is_input {
+            session.input.push(frame);
+        } else {
+            session.output.push(frame);
+        }
+    }
+    Ok(sessions)
+}
+
+/// Compares the replies by the `id` of their request, since concurrent requests may be
+/// answered in any order. Chunks of one reply keep their order.
+pub fn diff_replies(
+    recorded: &[serde_json::Value],
+    replayed: &[serde_json::Value],
+) -> Vec<ReplyDiff> {
+    fn by_id(frames: &[serde_json::Value]) -> BTreeMap<Option<String>, Vec<serde_json::Value>> {
+        let mut grouped: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        for frame in frames {
+            let id = frame
+                .get("id")
+                .and_then(|id| id.as_str())
+                .map(|id| id.to_owned());
+            grouped.entry(id).or_default().push(frame.to_owned());
+        }
+        grouped
+    }
+
+    let mut recorded = by_id(recorded);
+    let mut replayed = by_id(replayed);
+    let ids: BTreeSet<_> = recorded.keys().chain(replayed.keys()).cloned().collect();
+
+    ids.into_iter()
+        .filter_map(|id| {
+            let recorded = recorded.remove(&id).unwrap_or_default();
+            let replayed = replayed.remove(&id).unwrap_or_default();
+            (recorded != replayed).then_some(ReplyDiff {
+                id,
+                recorded,
+                replayed,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::recording::{diff_replies, Recorder, ReplyDiff};
+
+    #[test]
+    fn diff_by_id() {
+        let recorded = vec![
+            json!({"id": "1", "type": "config", "data": {"downloadSubdirectory": "edman"}}),
+            json!({"id": "2", "type": "report_progress"}),
+        ];
+        // Answered in a different order, and one reply changed
+        let replayed = vec![
+            json!({"id": "2", "type": "report_progress"}),
+            json!({"id": "1", "type": "config", "data": {"downloadSubdirectory": "other"}}),
+        ];
+
+        assert_eq!(
+            diff_replies(&recorded, &replayed),
+            vec![ReplyDiff {
+                id: Some("1".to_string()),
+                recorded: vec![recorded[0].to_owned()],
+                replayed: vec![replayed[1].to_owned()],
+            }]
+        );
+        assert!(diff_replies(&recorded, &recorded).is_empty());
+    }
+
+    #[test]
+    fn credentials_are_not_recorded() {
+        let path =
+            std::env::temp_dir().join(format!("edman-recording-{}.jsonl", std::process::id()));
+        let recorder = Recorder::create(&path, vec![]).unwrap();
+
+        recorder.record_in(&json!({
+            "id": "1",
+            "type": "enqueue_download",
+            "data": {"url": "https://example.com/a", "cookie": "session=secret-cookie"},
+        }));
+        recorder.record_out(&json!({
+            "id": "1",
+            "headers": [{"Authorization": "Bearer secret-token"}],
+        }));
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(!content.contains("secret-cookie"));
+        assert!(!content.contains("secret-token"));
+        assert!(content.contains("https://example.com/a"));
+        assert!(content.contains("[redacted]"));
+    }
+}
@@ -3,8 +3,8 @@ use std::path::PathBuf;
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let proto_dir = PathBuf::from("../../proto/");
 
+    // Clients are used by the test harness
     tonic_build::configure()
-        .build_client(false)
         .protoc_arg("--proto_path")
         .protoc_arg(proto_dir.to_str().unwrap())
         .protoc_arg("--experimental_allow_proto3_optional")
@@ -1,39 +1,68 @@
 use std::collections::{hash_map::RandomState, HashSet};
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use prisma_codegen::PrismaClient;
 
 use tonic::{Request, Response, Status};
 
-use crate::config::ConfigurationInterface;
+use crate::config::file::FileConfig;
 use crate::error_prisma_to_tonic;
-use crate::grpc::chrome_extension::{self, download_manager_server::DownloadManager};
+use crate::grpc::{
+    chrome_extension::{self, download_manager_server::DownloadManager},
+    ui,
+};
+use crate::manifests;
+use crate::progress::ProgressTracker;
+use crate::queue::Queue;
+use crate::storage::{self, FileRecord, FileStore, IncomingFile};
 
 pub struct ChromeExtensionInterface {
     pub prisma_client: Arc<PrismaClient>,
+    pub file_config: Arc<FileConfig>,
+    pub store: FileStore,
+    pub queue: Queue,
+    pub progress: ProgressTracker,
 }
 
 // TODO: Rust 1.74
 #[tonic::async_trait]
 impl DownloadManager for ChromeExtensionInterface {
+    async fn get_server_info(
+        &self,
+        _request: Request<chrome_extension::ServerInfoRequest>,
+    ) -> Result<Response<chrome_extension::ServerInfoReply>, Status> {
+        Ok(Response::new(chrome_extension::ServerInfoReply {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }))
+    }
     async fn get_config(
         &self,
-        _request: Request<chrome_extension::ConfigRequest>,
+        request: Request<chrome_extension::ConfigRequest>,
     ) -> Result<Response<chrome_extension::ConfigReply>, Status> {
-        let config = crate::config::Config::ensure_db(&self.prisma_client)
+        let profile = crate::config::profile_for_origin(
+            &self.prisma_client,
+            &self.file_config,
+            request.get_ref().origin.as_deref(),
+        )
+        .await
+        .map_err(error_prisma_to_tonic)?;
+        let config =
+            crate::config::resolve(&self.prisma_client, &self.file_config, &profile).await?;
+        let profiles = crate::config::list_profiles(&self.prisma_client, &self.file_config)
             .await
             .map_err(error_prisma_to_tonic)?;
 
         Ok(Response::new(chrome_extension::ConfigReply {
             config: Some(*config),
+            profile,
+            profiles,
         }))
     }
     async fn get_file_states(
         &self,
         request: Request<chrome_extension::GetFileStatesRequest>,
     ) -> Result<Response<chrome_extension::GetFileStatesReply>, Status> {
-        dbg!(request.get_ref());
-
         let params = request.get_ref();
         let records = self
             .prisma_client
@@ -62,18 +91,153 @@ impl DownloadManager for ChromeExtensionInterface {
         &self,
         request: Request<chrome_extension::RegisterFileRequest>,
     ) -> Result<Response<chrome_extension::RegisterFileReply>, Status> {
-        dbg!(request.get_ref());
+        let params = request.get_ref();
+        let (profile, config) = self.profile_for_origin(params.origin.as_deref()).await?;
 
+        let size = PathBuf::from(&config.save_file_directory)
+            .join(&params.path)
+            .metadata()
+            .map_err(|err| Status::not_found(format!("Cannot read {}: {}", params.path, err)))?
+            .len();
+        let id = self
+            .store
+            .create_record(
+                &config,
+                &profile,
+                params.origin.as_deref(),
+                FileRecord {
+                    key: &params.key,
+                    path: &params.path,
+                    size,
+                    page_origin: params.page_origin.as_deref(),
+                },
+            )
+            .await?;
+
+        let reply = chrome_extension::RegisterFileReply { id };
+        Ok(Response::new(reply))
+    }
+    async fn commit_download(
+        &self,
+        request: Request<chrome_extension::CommitDownloadRequest>,
+    ) -> Result<Response<chrome_extension::CommitDownloadReply>, Status> {
         let params = request.get_ref();
-        let record = self
-            .prisma_client
-            .file()
-            .create(params.key.to_owned(), params.path.to_owned(), vec![])
-            .exec()
+        let (profile, config) = self.profile_for_origin(params.origin.as_deref()).await?;
+
+        let source = storage::download_source(&config, &params.download_path)?;
+        let reply = self
+            .store
+            .store_file(
+                &config,
+                &profile,
+                params.origin.as_deref(),
+                IncomingFile {
+                    source: &source,
+                    save_path: &params.save_path,
+                    key: &params.key,
+                    page_origin: params.page_origin.as_deref(),
+                },
+            )
+            .await?;
+        Ok(Response::new(reply))
+    }
+    async fn register_files(
+        &self,
+        request: Request<chrome_extension::RegisterFilesRequest>,
+    ) -> Result<Response<chrome_extension::RegisterFilesReply>, Status> {
+        let params = request.get_ref();
+        let (profile, config) = self.profile_for_origin(params.origin.as_deref()).await?;
+
+        let results = self
+            .store
+            .store_files(&config, &profile, params.origin.as_deref(), &params.files)
+            .await?;
+
+        let results = results
+            .into_iter()
+            .map(|result| match result {
+                Ok(reply) => chrome_extension::RegisterFilesResult {
+                    file: Some(reply),
+                    error: None,
+                },
+                Err(status) => chrome_extension::RegisterFilesResult {
+                    file: None,
+                    error: Some(chrome_extension::FileError {
+                        code: status.code() as i32,
+                        message: status.message().to_string(),
+                    }),
+                },
+            })
+            .collect();
+        Ok(Response::new(chrome_extension::RegisterFilesReply {
+            results,
+        }))
+    }
+    async fn enqueue_download(
+        &self,
+        request: Request<chrome_extension::EnqueueDownloadRequest>,
+    ) -> Result<Response<chrome_extension::EnqueueDownloadReply>, Status> {
+        let params = request.get_ref();
+        let (profile, _) = self.profile_for_origin(params.origin.as_deref()).await?;
+
+        let job_id = self.queue.enqueue(&profile, params).await?;
+
+        Ok(Response::new(chrome_extension::EnqueueDownloadReply {
+            job_id,
+        }))
+    }
+    async fn report_progress(
+        &self,
+        request: Request<chrome_extension::ReportProgressRequest>,
+    ) -> Result<Response<chrome_extension::ReportProgressReply>, Status> {
+        let params = request.into_inner();
+
+        self.progress.report(ui::DownloadProgress {
+            origin: params.origin.unwrap_or_default(),
+            download_id: params.download_id,
+            url: params.url,
+            filename: params.filename,
+            bytes_received: params.bytes_received,
+            total_bytes: params.total_bytes,
+            // Both enums use the same values
+            state: params.state,
+            error: params.error,
+        });
+
+        Ok(Response::new(chrome_extension::ReportProgressReply {}))
+    }
+    async fn register_manifest(
+        &self,
+        request: Request<chrome_extension::RegisterManifestRequest>,
+    ) -> Result<Response<chrome_extension::RegisterManifestReply>, Status> {
+        let params = request.into_inner();
+        let kind = params.kind();
+        if !manifests::is_manifest_file(&params.browser, std::path::Path::new(&params.path), kind) {
+            return Err(Status::invalid_argument(format!(
+                "path is not where a manifest for {} is installed.",
+                params.browser
+            )));
+        }
+
+        manifests::register(&self.prisma_client, params.browser, params.path, kind)
             .await
             .map_err(error_prisma_to_tonic)?;
 
-        let reply = chrome_extension::RegisterFileReply { id: record.id };
-        Ok(Response::new(reply))
+        Ok(Response::new(chrome_extension::RegisterManifestReply {}))
+    }
+}
+
+impl ChromeExtensionInterface {
+    async fn profile_for_origin(
+        &self,
+        origin: Option<&str>,
+    ) -> Result<(String, Box<crate::config::Config>), Status> {
+        let profile =
+            crate::config::profile_for_origin(&self.prisma_client, &self.file_config, origin)
+                .await
+                .map_err(error_prisma_to_tonic)?;
+        let config =
+            crate::config::resolve(&self.prisma_client, &self.file_config, &profile).await?;
+        Ok((profile, config))
     }
 }
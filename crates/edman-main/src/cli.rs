@@ -0,0 +1,188 @@
+use std::{collections::BTreeSet, io::Write, net::SocketAddr, path::PathBuf};
+
+use clap::{Parser, Subcommand};
+use prisma_codegen::PrismaClient;
+
+use crate::config::{
+    file::{FileConfig, FileValues},
+    Config, ConfigurationInterface,
+};
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Maximum number of queued downloads fetched at the same time
+    #[arg(long, default_value_t = 4)]
+    pub max_downloads: usize,
+
+    /// Maximum number of queued downloads fetched from the same host at the same time
+    #[arg(long, default_value_t = 2)]
+    pub max_downloads_per_host: usize,
+
+    /// Also listen on this TCP address, e.g. 0.0.0.0:50051, for edman-ui on other machines.
+    /// Connections use TLS and have to send the token. Browsers cannot connect there, and the
+    /// configuration cannot be changed from there.
+    #[arg(long, requires_all = ["tls_cert", "tls_key", "token"])]
+    pub listen_tcp: Option<SocketAddr>,
+
+    /// PEM certificate chain for the TCP listener
+    #[arg(long)]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key for the TCP listener
+    #[arg(long)]
+    pub tls_key: Option<PathBuf>,
+
+    /// Token that clients of the TCP listener have to send
+    #[arg(long, env = transport::tcp::TOKEN_VAR, hide_env_values = true)]
+    pub token: Option<String>,
+}
+
+impl Cli {
+    pub fn tcp_options(&self) -> Option<transport::tcp::TcpOptions> {
+        Some(transport::tcp::TcpOptions {
+            addr: self.listen_tcp?,
+            cert: self.tls_cert.to_owned()?,
+            key: self.tls_key.to_owned()?,
+            token: self.token.to_owned()?,
+        })
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Inspect or provision the configuration
+    #[command(subcommand)]
+    Config(ConfigCommand),
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Write the effective configuration as TOML to the given file, or to stdout
+    Export { path: Option<PathBuf> },
+    /// Store the configuration from the given TOML file (edman.toml by default) into the database
+    Import { path: Option<PathBuf> },
+    /// Show which values come from the config file, the database or the defaults
+    Show,
+}
+
+pub async fn run_config_command(
+    command: ConfigCommand,
+    client: &PrismaClient,
+    file_config: &FileConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        ConfigCommand::Export { path } => {
+            let profiles = crate::config::list_profiles(client, file_config).await?;
+            let exported = FileConfig {
+                active_profile: profiles
+                    .iter()
+                    .find(|profile| profile.active)
+                    .map(|profile| profile.name.to_owned()),
+                profiles: profiles
+                    .iter()
+                    .filter_map(|profile| {
+                        let config = profile.config.as_ref()?;
+                        Some((profile.name.to_owned(), FileValues::from(config)))
+                    })
+                    .collect(),
+                ..Default::default()
+            };
+
+            let content = exported.to_toml()?;
+            match path {
+                Some(path) => std::fs::File::create(path)?.write_all(content.as_bytes())?,
+                None => print!("{}", content),
+            }
+        }
+        ConfigCommand::Import { path } => {
+            let path = path.unwrap_or_else(utils::config_file_path);
+            let imported = FileConfig::load(&path)?;
+
+            let stored = crate::config::list_profiles(client, &FileConfig::default()).await?;
+            let names: BTreeSet<String> = stored
+                .into_iter()
+                .map(|profile| profile.name)
+                .chain(imported.profiles.keys().cloned())
+                .collect();
+
+            for name in names {
+                let mut config = Config::read_db(client, &name)
+                    .await?
+                    .map_or_else(crate::config::default, |config| *config);
+                imported.apply(&name, &mut config);
+                Config::update_db(client, &name, config).await?;
+            }
+            if let Some(ref active) = imported.active_profile {
+                // Stored with the default values if the file only pins it
+                crate::config::switch_profile(client, &imported, active).await?;
+            }
+
+            println!("Imported {}", path.display());
+        }
+        ConfigCommand::Show => show(client, file_config, &mut std::io::stdout()).await?,
+    }
+
+    Ok(())
+}
+
+/// Writes every profile with the source of each of its values.
+async fn show(
+    client: &PrismaClient,
+    file_config: &FileConfig,
+    out: &mut impl Write,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let profiles = crate::config::list_profiles(client, file_config).await?;
+
+    for profile in profiles {
+        let Some(ref config) = profile.config else {
+            continue;
+        };
+        let values = FileValues::from(config);
+        let values = toml::to_string_pretty(&values)?;
+        let sources = crate::config::sources(client, file_config, &profile.name).await?;
+
+        writeln!(
+            out,
+            "[profiles.{}]{}",
+            profile.name,
+            if profile.active { " # active" } else { "" }
+        )?;
+        for line in values.lines() {
+            let source = sources
+                .iter()
+                .find(|(key, _)| line.starts_with(&format!("{} =", key)));
+            match source {
+                Some((_, source)) => writeln!(out, "{:<60} # {}", line, source)?,
+                None => writeln!(out, "{}", line)?,
+            }
+        }
+        writeln!(out)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::file::FileConfig;
+    use crate::temp_db::TempDatabase;
+
+    #[tokio::test]
+    async fn show_reports_defaults_on_a_fresh_database() {
+        let database = TempDatabase::create().await.unwrap();
+
+        let mut out = vec![];
+        crate::cli::show(&database.client, &FileConfig::default(), &mut out)
+            .await
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.starts_with("[profiles.default] # active"));
+        assert!(out.contains("# default"));
+        assert!(!out.contains("# database"));
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    ffi::OsString,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::config::CollisionPolicy;
+
+/// Decides where `source` should be moved to when `target` may already exist.
+///
+/// Returns `Ok(None)` if the file should not be saved at all.
+pub fn resolve(
+    policy: CollisionPolicy,
+    source: &Path,
+    target: PathBuf,
+) -> std::io::Result<Option<PathBuf>> {
+    if !target.try_exists()? {
+        return Ok(Some(target));
+    }
+
+    match policy {
+        CollisionPolicy::Overwrite => Ok(Some(target)),
+        CollisionPolicy::AutoSuffix => Ok(Some(numbered(&target))),
+        CollisionPolicy::HashSuffix => {
+            let hash = file_hash(source)?;
+            let hashed = with_suffix(&target, &format!("-{}", &hash[..8]));
+            // The suffix is short enough to collide, so only a file with the same full
+            // hash has the same content and may be replaced.
+            if !hashed.try_exists()? || file_hash(&hashed)? == hash {
+                return Ok(Some(hashed));
+            }
+            Ok(Some(numbered(&hashed)))
+        }
+        CollisionPolicy::Skip => Ok(None),
+        CollisionPolicy::Fail => Err(std::io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("\"{}\" already exists", target.display()),
+        )),
+    }
+}
+
+/// First of `path` with ` (1)`, ` (2)`, ... that does not exist yet
+fn numbered(path: &Path) -> PathBuf {
+    (1..)
+        .map(|i| with_suffix(path, &format!(" ({})", i)))
+        .find(|path| !path.exists())
+        .expect("Ran out of suffixes")
+}
+
+/// Inserts `suffix` between the file stem and the extension.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut file_name = OsString::new();
+    if let Some(stem) = path.file_stem() {
+        file_name.push(stem);
+    }
+    file_name.push(suffix);
+    if let Some(extension) = path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    path.with_file_name(file_name)
+}
+
+/// Hex encoded SHA-256 of the content of `path`
+fn file_hash(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)?;
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use crate::collision::{file_hash, resolve, with_suffix};
+    use crate::config::CollisionPolicy;
+
+    #[test]
+    fn suffix_position() {
+        assert_eq!(
+            with_suffix(Path::new("a/b/c.txt"), " (1)"),
+            PathBuf::from("a/b/c (1).txt")
+        );
+        assert_eq!(
+            with_suffix(Path::new("a/b/c"), "-0a1b2c3d"),
+            PathBuf::from("a/b/c-0a1b2c3d")
+        );
+    }
+
+    #[test]
+    fn auto_suffix() -> std::io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("edman-collision-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join("c.txt"), "old")?;
+        std::fs::write(dir.join("c (1).txt"), "old")?;
+        std::fs::write(dir.join("new.txt"), "new")?;
+
+        let resolved = resolve(
+            CollisionPolicy::AutoSuffix,
+            &dir.join("new.txt"),
+            dir.join("c.txt"),
+        )?;
+        assert_eq!(resolved, Some(dir.join("c (2).txt")));
+
+        let skipped = resolve(CollisionPolicy::Skip, &dir.join("new.txt"), dir.join("c.txt"))?;
+        assert_eq!(skipped, None);
+
+        std::fs::remove_dir_all(dir)
+    }
+
+    #[test]
+    fn hash_suffix_keeps_different_content() -> std::io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("edman-hash-suffix-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join("c.txt"), "old")?;
+        std::fs::write(dir.join("new.txt"), "new")?;
+        let hash = file_hash(&dir.join("new.txt"))?;
+        let hashed = dir.join(format!("c-{}.txt", &hash[..8]));
+
+        // Same suffix, but different content
+        std::fs::write(&hashed, "other")?;
+        let resolved = resolve(
+            CollisionPolicy::HashSuffix,
+            &dir.join("new.txt"),
+            dir.join("c.txt"),
+        )?;
+        assert_eq!(
+            resolved,
+            Some(dir.join(format!("c-{} (1).txt", &hash[..8])))
+        );
+
+        // Same content, which may be replaced
+        std::fs::write(&hashed, "new")?;
+        let resolved = resolve(
+            CollisionPolicy::HashSuffix,
+            &dir.join("new.txt"),
+            dir.join("c.txt"),
+        )?;
+        assert_eq!(resolved, Some(hashed));
+
+        std::fs::remove_dir_all(dir)
+    }
+}
@@ -0,0 +1,353 @@
+use std::{collections::BTreeMap, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::grpc::config::{CollisionPolicy, Config, OriginQuota};
+
+/// Contents of `edman.toml`.
+///
+/// Every key is optional. Keys present in the file take precedence over the database.
+/// Top-level values apply to every profile, and `[profiles.<name>]` tables override them.
+/// Unknown keys are rejected by [`FileConfig::from_toml`], as serde cannot deny them next to
+/// the flattened `shared`.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+pub struct FileConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active_profile: Option<String>,
+    #[serde(flatten)]
+    pub shared: FileValues,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, FileValues>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+pub struct FileValues {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_directory: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_subdirectory: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub save_file_directory: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_origins: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_extensions: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub collision_policy: Option<FileCollisionPolicy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub save_quota_bytes: Option<u64>,
+    /// Maps origins to their quota in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin_quotas: Option<BTreeMap<String, u64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota_warning_percent: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FileCollisionPolicy {
+    Overwrite,
+    AutoSuffix,
+    HashSuffix,
+    Skip,
+    Fail,
+}
+
+impl From<CollisionPolicy> for FileCollisionPolicy {
+    fn from(value: CollisionPolicy) -> Self {
+        match value {
+            CollisionPolicy::Overwrite => Self::Overwrite,
+            CollisionPolicy::AutoSuffix => Self::AutoSuffix,
+            CollisionPolicy::HashSuffix => Self::HashSuffix,
+            CollisionPolicy::Skip => Self::Skip,
+            CollisionPolicy::Fail => Self::Fail,
+        }
+    }
+}
+
+impl From<FileCollisionPolicy> for CollisionPolicy {
+    fn from(value: FileCollisionPolicy) -> Self {
+        match value {
+            FileCollisionPolicy::Overwrite => Self::Overwrite,
+            FileCollisionPolicy::AutoSuffix => Self::AutoSuffix,
+            FileCollisionPolicy::HashSuffix => Self::HashSuffix,
+            FileCollisionPolicy::Skip => Self::Skip,
+            FileCollisionPolicy::Fail => Self::Fail,
+        }
+    }
+}
+
+impl From<&Config> for FileValues {
+    fn from(config: &Config) -> Self {
+        Self {
+            download_directory: Some(config.download_directory.to_owned()),
+            download_subdirectory: Some(config.download_subdirectory.to_owned()),
+            save_file_directory: Some(config.save_file_directory.to_owned()),
+            allowed_origins: Some(config.allowed_origins.to_owned()),
+            allowed_extensions: Some(config.allowed_extensions.to_owned()),
+            collision_policy: Some(config.collision_policy().into()),
+            save_quota_bytes: Some(config.save_quota_bytes),
+            origin_quotas: Some(
+                config
+                    .origin_quotas
+                    .iter()
+                    .map(|quota| (quota.origin.to_owned(), quota.bytes))
+                    .collect(),
+            ),
+            quota_warning_percent: Some(config.quota_warning_percent),
+        }
+    }
+}
+
+/// Where a configuration value was taken from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigSource {
+    Default,
+    Database,
+    File,
+}
+
+impl std::fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ConfigSource::Default => "default",
+            ConfigSource::Database => "database",
+            ConfigSource::File => "file",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FileConfig {
+    /// Reads the file at `path`. A missing file is treated as an empty one.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err.into()),
+        };
+
+        Self::from_toml(&content)
+            .map_err(|err| format!("Could not parse {}: {}", path.as_ref().display(), err).into())
+    }
+
+    /// Parses `content`, failing on keys that would otherwise be ignored, like misspelled ones.
+    pub fn from_toml(content: &str) -> Result<Self, String> {
+        let table: toml::Table = toml::from_str(content).map_err(|err| err.to_string())?;
+        let unknown = unknown_keys(&table);
+        if !unknown.is_empty() {
+            return Err(format!("Unknown keys {}", unknown.join(", ")));
+        }
+        toml::Value::Table(table)
+            .try_into()
+            .map_err(|err: toml::de::Error| err.to_string())
+    }
+
+    pub fn to_toml(&self) -> Result<String, toml::ser::Error> {
+        toml::to_string_pretty(self)
+    }
+
+    /// Overwrites the values of `config` with the keys this file sets for `profile`.
+    pub fn apply(&self, profile: &str, config: &mut Config) {
+        self.shared.apply(config);
+        if let Some(values) = self.profiles.get(profile) {
+            values.apply(config);
+        }
+    }
+
+    /// Source of every configuration key of `profile`.
+    /// `in_db` tells whether the database has a stored config for it.
+    pub fn sources(&self, profile: &str, in_db: bool) -> Vec<(&'static str, ConfigSource)> {
+        let profile_keys = self.profiles.get(profile).map(FileValues::keys);
+
+        self.shared
+            .keys()
+            .into_iter()
+            .enumerate()
+            .map(|(i, (key, in_shared))| {
+                let in_file = in_shared || matches!(profile_keys, Some(keys) if keys[i].1);
+                let source = if in_file {
+                    ConfigSource::File
+                } else if in_db {
+                    ConfigSource::Database
+                } else {
+                    ConfigSource::Default
+                };
+                (key, source)
+            })
+            .collect()
+    }
+
+    /// Whether the file sets anything at all
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+impl FileValues {
+    /// Overwrites the values of `config` with the keys present here.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(ref v) = self.download_directory {
+            config.download_directory = v.to_owned();
+        }
+        if let Some(ref v) = self.download_subdirectory {
+            config.download_subdirectory = v.to_owned();
+        }
+        if let Some(ref v) = self.save_file_directory {
+            config.save_file_directory = v.to_owned();
+        }
+        if let Some(ref v) = self.allowed_origins {
+            config.allowed_origins = v.to_owned();
+        }
+        if let Some(ref v) = self.allowed_extensions {
+            config.allowed_extensions = v.to_owned();
+        }
+        if let Some(v) = self.collision_policy {
+            config.set_collision_policy(v.into());
+        }
+        if let Some(v) = self.save_quota_bytes {
+            config.save_quota_bytes = v;
+        }
+        if let Some(ref v) = self.origin_quotas {
+            config.origin_quotas = v
+                .iter()
+                .map(|(origin, bytes)| OriginQuota {
+                    origin: origin.to_owned(),
+                    bytes: *bytes,
+                })
+                .collect();
+        }
+        if let Some(v) = self.quota_warning_percent {
+            config.quota_warning_percent = v;
+        }
+    }
+
+    /// Names of all configuration keys, paired with whether they are set here.
+    pub fn keys(&self) -> [(&'static str, bool); 9] {
+        [
+            ("download_directory", self.download_directory.is_some()),
+            (
+                "download_subdirectory",
+                self.download_subdirectory.is_some(),
+            ),
+            ("save_file_directory", self.save_file_directory.is_some()),
+            ("allowed_origins", self.allowed_origins.is_some()),
+            ("allowed_extensions", self.allowed_extensions.is_some()),
+            ("collision_policy", self.collision_policy.is_some()),
+            ("save_quota_bytes", self.save_quota_bytes.is_some()),
+            ("origin_quotas", self.origin_quotas.is_some()),
+            (
+                "quota_warning_percent",
+                self.quota_warning_percent.is_some(),
+            ),
+        ]
+    }
+}
+
+/// Keys of `table` that no field of [`FileConfig`] reads, as dotted paths.
+fn unknown_keys(table: &toml::Table) -> Vec<String> {
+    let values = FileValues::default().keys().map(|(key, _)| key);
+    let is_value = |key: &str| values.contains(&key);
+
+    let mut unknown = vec![];
+    for (key, value) in table {
+        match key.as_str() {
+            "active_profile" => {}
+            "profiles" => {
+                for (name, profile) in value.as_table().into_iter().flatten() {
+                    for key in profile
+                        .as_table()
+                        .into_iter()
+                        .flat_map(|profile| profile.keys())
+                    {
+                        if !is_value(key) {
+                            unknown.push(format!("profiles.{}.{}", name, key));
+                        }
+                    }
+                }
+            }
+            key if !is_value(key) => unknown.push(key.to_owned()),
+            _ => {}
+        }
+    }
+    unknown
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use crate::config::file::{FileCollisionPolicy, FileConfig, FileValues};
+    use crate::grpc::config::{CollisionPolicy, Config, OriginQuota};
+
+    #[test]
+    fn round_trip() {
+        let config = Config {
+            download_directory: "/home/user/Downloads".to_string(),
+            download_subdirectory: "edman".to_string(),
+            save_file_directory: "/srv/archive".to_string(),
+            allowed_origins: vec!["chrome-extension://abc/".to_string()],
+            allowed_extensions: vec![],
+            collision_policy: CollisionPolicy::AutoSuffix.into(),
+            save_quota_bytes: 1 << 30,
+            origin_quotas: vec![OriginQuota {
+                origin: "chrome-extension://abc/".to_string(),
+                bytes: 1 << 20,
+            }],
+            quota_warning_percent: 90,
+        };
+
+        let exported = FileConfig {
+            active_profile: Some("work".to_string()),
+            profiles: BTreeMap::from([("work".to_string(), FileValues::from(&config))]),
+            ..Default::default()
+        }
+        .to_toml()
+        .unwrap();
+        let imported: FileConfig = toml::from_str(&exported).unwrap();
+
+        let mut applied = Config::default();
+        imported.apply("work", &mut applied);
+        assert_eq!(applied, config);
+        assert_eq!(imported.active_profile.as_deref(), Some("work"));
+    }
+
+    #[test]
+    fn profile_overrides_shared() {
+        let file: FileConfig = toml::from_str(
+            "save_file_directory = \"/srv/archive\"\ncollision_policy = \"skip\"\n\n[profiles.work]\nsave_file_directory = \"/srv/work\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            file.shared,
+            FileValues {
+                save_file_directory: Some("/srv/archive".to_string()),
+                collision_policy: Some(FileCollisionPolicy::Skip),
+                ..Default::default()
+            }
+        );
+
+        let mut work = Config::default();
+        file.apply("work", &mut work);
+        assert_eq!(work.save_file_directory, "/srv/work");
+        assert_eq!(work.collision_policy(), CollisionPolicy::Skip);
+
+        let mut personal = Config::default();
+        file.apply("personal", &mut personal);
+        assert_eq!(personal.save_file_directory, "/srv/archive");
+    }
+
+    #[test]
+    fn misspelled_keys_are_rejected() {
+        let err = FileConfig::from_toml(
+            "save_file_directroy = \"/srv/archive\"\n\n[profiles.work]\ncollision = \"skip\"\n",
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            err,
+            "Unknown keys profiles.work.collision, save_file_directroy"
+        );
+    }
+}
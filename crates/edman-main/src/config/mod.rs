@@ -0,0 +1,438 @@
+use prisma_client_rust::QueryError;
+use tonic::Status;
+
+use crate::{error_prisma_to_tonic, PrismaClient};
+
+pub use crate::grpc::config::{CollisionPolicy, Config, OriginQuota, Profile};
+
+use self::file::{ConfigSource, FileConfig};
+
+pub mod file;
+
+pub const DEFAULT_PROFILE: &str = "default";
+
+// TODO: Rust 1.74
+#[tonic::async_trait]
+pub trait ConfigurationInterface {
+    async fn read_db(client: &PrismaClient, profile: &str)
+        -> Result<Option<Box<Self>>, QueryError>;
+    /// Stores `config` as profile `profile`, which is created if it is not stored yet.
+    async fn update_db(
+        client: &PrismaClient,
+        profile: &str,
+        config: Self,
+    ) -> Result<(), QueryError>;
+}
+
+// TODO: Rust 1.74
+#[tonic::async_trait]
+impl ConfigurationInterface for Config {
+    async fn read_db(
+        client: &PrismaClient,
+        profile: &str,
+    ) -> Result<Option<Box<Self>>, QueryError> {
+        Ok(db_read(client, profile).await?.map(Box::new))
+    }
+    async fn update_db(
+        client: &PrismaClient,
+        profile: &str,
+        config: Self,
+    ) -> Result<(), QueryError> {
+        if db_read(client, profile).await?.is_some() {
+            return db_update_all(client, profile, config).await;
+        }
+
+        // The first stored profile is the active one
+        let is_first = client.config().count(vec![]).exec().await? == 0;
+        db_write_all(client, profile, is_first, config).await
+    }
+}
+
+/// Reads profile `profile` with the values from `edman.toml` applied.
+///
+/// The active profile and the profiles of `edman.toml` have the default values until they
+/// are stored. Any other profile that is not stored is `NotFound`.
+pub async fn resolve(
+    client: &PrismaClient,
+    file_config: &FileConfig,
+    profile: &str,
+) -> Result<Box<Config>, Status> {
+    let stored = db_read(client, profile)
+        .await
+        .map_err(error_prisma_to_tonic)?;
+    let mut config = match stored {
+        Some(config) => config,
+        None if is_known(client, file_config, profile)
+            .await
+            .map_err(error_prisma_to_tonic)? =>
+        {
+            default()
+        }
+        None => {
+            return Err(Status::not_found(format!(
+                "Profile \"{}\" not found",
+                profile
+            )))
+        }
+    };
+    file_config.apply(profile, &mut config);
+    Ok(Box::new(config))
+}
+
+/// Whether `profile` exists without being stored
+async fn is_known(
+    client: &PrismaClient,
+    file_config: &FileConfig,
+    profile: &str,
+) -> Result<bool, QueryError> {
+    Ok(file_config.profiles.contains_key(profile)
+        || active_profile(client, file_config).await? == profile)
+}
+
+/// Reports where each value of the resolved profile comes from.
+pub async fn sources(
+    client: &PrismaClient,
+    file_config: &FileConfig,
+    profile: &str,
+) -> Result<Vec<(&'static str, ConfigSource)>, QueryError> {
+    let in_db = db_read(client, profile).await?.is_some();
+    Ok(file_config.sources(profile, in_db))
+}
+
+/// Name of the active profile. `active_profile` in `edman.toml` takes precedence.
+pub async fn active_profile(
+    client: &PrismaClient,
+    file_config: &FileConfig,
+) -> Result<String, QueryError> {
+    if let Some(ref name) = file_config.active_profile {
+        return Ok(name.to_owned());
+    }
+
+    let active = client
+        .config()
+        .find_first(vec![prisma_codegen::config::active::equals(true)])
+        .exec()
+        .await?;
+
+    Ok(active
+        .map(|config| config.name)
+        .unwrap_or_else(|| DEFAULT_PROFILE.to_string()))
+}
+
+/// Picks the profile whose allow-lists contain `origin`, falling back to the active profile.
+pub async fn profile_for_origin(
+    client: &PrismaClient,
+    file_config: &FileConfig,
+    origin: Option<&str>,
+) -> Result<String, QueryError> {
+    let active = active_profile(client, file_config).await?;
+    let Some(origin) = origin else {
+        return Ok(active);
+    };
+
+    let profiles = list_profiles(client, file_config).await?;
+    let allowing = |profile: &&Profile| {
+        profile.config.as_ref().map_or(false, |config| {
+            config.allowed_origins.iter().any(|o| o == origin)
+                || config.allowed_extensions.iter().any(|e| e == origin)
+        })
+    };
+
+    // Prefer the active profile if several profiles allow the same origin
+    let selected = profiles
+        .iter()
+        .filter(allowing)
+        .find(|profile| profile.active)
+        .or_else(|| profiles.iter().find(allowing));
+
+    Ok(selected
+        .map(|profile| profile.name.to_owned())
+        .unwrap_or(active))
+}
+
+/// Lists the stored profiles, followed by the active profile and the profiles of `edman.toml`
+/// that are not stored yet.
+pub async fn list_profiles(
+    client: &PrismaClient,
+    file_config: &FileConfig,
+) -> Result<Vec<Profile>, QueryError> {
+    let active = active_profile(client, file_config).await?;
+
+    let records = client
+        .config()
+        .find_many(vec![])
+        .order_by(prisma_codegen::config::id::order(
+            prisma_client_rust::Direction::Asc,
+        ))
+        .exec()
+        .await?;
+    let mut profiles: Vec<(String, Config)> = records
+        .into_iter()
+        .map(|record| (record.name.to_owned(), from_record(record)))
+        .collect();
+    for name in std::iter::once(&active).chain(file_config.profiles.keys()) {
+        if !profiles.iter().any(|(stored, _)| stored == name) {
+            profiles.push((name.to_owned(), default()));
+        }
+    }
+
+    Ok(profiles
+        .into_iter()
+        .map(|(name, mut config)| {
+            file_config.apply(&name, &mut config);
+            Profile {
+                active: name == active,
+                name,
+                config: Some(config),
+            }
+        })
+        .collect())
+}
+
+pub async fn create_profile(
+    client: &PrismaClient,
+    profile: &str,
+    config: Config,
+) -> Result<(), QueryError> {
+    db_write_all(client, profile, false, config).await
+}
+
+/// Makes `profile` the active one. A profile that only exists in `edman.toml` is stored
+/// with the default values first, and any other profile that is not stored is `NotFound`.
+pub async fn switch_profile(
+    client: &PrismaClient,
+    file_config: &FileConfig,
+    profile: &str,
+) -> Result<(), Status> {
+    let stored = db_read(client, profile)
+        .await
+        .map_err(error_prisma_to_tonic)?;
+    if stored.is_none() {
+        if !is_known(client, file_config, profile)
+            .await
+            .map_err(error_prisma_to_tonic)?
+        {
+            return Err(Status::not_found(format!(
+                "Profile \"{}\" not found",
+                profile
+            )));
+        }
+        db_write_all(client, profile, false, default())
+            .await
+            .map_err(error_prisma_to_tonic)?;
+    }
+
+    client
+        ._batch((
+            client.config().update_many(
+                vec![],
+                vec![prisma_codegen::config::SetParam::SetActive(false)],
+            ),
+            client.config().update(
+                prisma_codegen::config::UniqueWhereParam::NameEquals(profile.to_string()),
+                vec![prisma_codegen::config::SetParam::SetActive(true)],
+            ),
+        ))
+        .await
+        .map_err(error_prisma_to_tonic)?;
+    Ok(())
+}
+
+async fn db_read(client: &PrismaClient, profile: &str) -> Result<Option<Config>, QueryError> {
+    let config = client
+        .config()
+        .find_unique(prisma_codegen::config::UniqueWhereParam::NameEquals(
+            profile.to_string(),
+        ))
+        .exec()
+        .await?;
+
+    Ok(config.map(from_record))
+}
+
+fn from_record(config: prisma_codegen::config::Data) -> Config {
+    Config {
+        download_directory: config.download_directory,
+        download_subdirectory: config.download_subdirectory,
+        save_file_directory: config.save_file_directory,
+        allowed_origins: config
+            .allowed_origins
+            .split('\n')
+            .filter(|s| !s.is_empty())
+            .map(ToOwned::to_owned)
+            .collect(),
+        allowed_extensions: config
+            .allowed_extensions
+            .split('\n')
+            .filter(|s| !s.is_empty())
+            .map(ToOwned::to_owned)
+            .collect(),
+        collision_policy: config.collision_policy,
+        save_quota_bytes: config.save_quota_bytes as u64,
+        origin_quotas: config
+            .origin_quotas
+            .split('\n')
+            .filter_map(|line| {
+                let (origin, bytes) = line.rsplit_once('\t')?;
+                Some(OriginQuota {
+                    origin: origin.to_owned(),
+                    bytes: bytes.parse().ok()?,
+                })
+            })
+            .collect(),
+        quota_warning_percent: config.quota_warning_percent as u32,
+    }
+}
+
+fn join_origin_quotas(quotas: &[OriginQuota]) -> String {
+    quotas
+        .iter()
+        .map(|quota| format!("{}\t{}", quota.origin, quota.bytes))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+async fn db_write_all(
+    client: &PrismaClient,
+    profile: &str,
+    active: bool,
+    config: Config,
+) -> Result<(), QueryError> {
+    client
+        .config()
+        .create(
+            config.download_directory,
+            config.download_subdirectory,
+            config.save_file_directory,
+            config.allowed_extensions.join("\n"),
+            config.allowed_origins.join("\n"),
+            vec![
+                prisma_codegen::config::SetParam::SetName(profile.to_string()),
+                prisma_codegen::config::SetParam::SetActive(active),
+                prisma_codegen::config::SetParam::SetCollisionPolicy(config.collision_policy),
+                prisma_codegen::config::SetParam::SetSaveQuotaBytes(config.save_quota_bytes as i64),
+                prisma_codegen::config::SetParam::SetOriginQuotas(join_origin_quotas(
+                    &config.origin_quotas,
+                )),
+                prisma_codegen::config::SetParam::SetQuotaWarningPercent(
+                    config.quota_warning_percent as i32,
+                ),
+            ],
+        )
+        .exec()
+        .await?;
+    Ok(())
+}
+
+async fn db_update_all(
+    client: &PrismaClient,
+    profile: &str,
+    config: Config,
+) -> Result<(), QueryError> {
+    client
+        .config()
+        .update(
+            prisma_codegen::config::UniqueWhereParam::NameEquals(profile.to_string()),
+            vec![
+                prisma_codegen::config::SetParam::SetDownloadDirectory(config.download_directory),
+                prisma_codegen::config::SetParam::SetDownloadSubdirectory(
+                    config.download_subdirectory,
+                ),
+                prisma_codegen::config::SetParam::SetSaveFileDirectory(config.save_file_directory),
+                prisma_codegen::config::SetParam::SetAllowedExtensions(
+                    config.allowed_extensions.join("\n"),
+                ),
+                prisma_codegen::config::SetParam::SetAllowedOrigins(
+                    config.allowed_origins.join("\n"),
+                ),
+                prisma_codegen::config::SetParam::SetCollisionPolicy(config.collision_policy),
+                prisma_codegen::config::SetParam::SetSaveQuotaBytes(config.save_quota_bytes as i64),
+                prisma_codegen::config::SetParam::SetOriginQuotas(join_origin_quotas(
+                    &config.origin_quotas,
+                )),
+                prisma_codegen::config::SetParam::SetQuotaWarningPercent(
+                    config.quota_warning_percent as i32,
+                ),
+            ],
+        )
+        .exec()
+        .await?;
+    Ok(())
+}
+
+pub fn default() -> Config {
+    let user_dirs = directories::UserDirs::new();
+
+    Config {
+        download_directory: user_dirs
+            .as_ref()
+            .and_then(|user_dirs| user_dirs.download_dir())
+            .and_then(|download_dir| download_dir.to_str())
+            .map(|s| s.to_string())
+            .unwrap_or("".to_string()),
+        download_subdirectory: "edman".to_string(),
+        // Not the working directory, which depends on who started edman-main
+        save_file_directory: utils::default_save_file_directory()
+            .to_string_lossy()
+            .into_owned(),
+        allowed_extensions: vec![],
+        allowed_origins: vec![],
+        collision_policy: CollisionPolicy::Overwrite.into(),
+        save_quota_bytes: 0,
+        origin_quotas: vec![],
+        quota_warning_percent: 90,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::Code;
+
+    use crate::config::file::FileConfig;
+    use crate::temp_db::TempDatabase;
+
+    #[tokio::test]
+    async fn profiles_are_not_stored_implicitly() {
+        let database = TempDatabase::create().await.unwrap();
+        let client = &database.client;
+        let file_config = FileConfig::default();
+
+        crate::config::resolve(client, &file_config, crate::config::DEFAULT_PROFILE)
+            .await
+            .unwrap();
+        let unknown = crate::config::resolve(client, &file_config, "unknown")
+            .await
+            .unwrap_err();
+        assert_eq!(unknown.code(), Code::NotFound);
+
+        let profiles = crate::config::list_profiles(client, &file_config)
+            .await
+            .unwrap();
+        assert_eq!(profiles.len(), 1);
+        assert!(profiles[0].active);
+        assert_eq!(client.config().count(vec![]).exec().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn switching_stores_profiles_of_the_file() {
+        let database = TempDatabase::create().await.unwrap();
+        let client = &database.client;
+        let file_config: FileConfig = toml::from_str("[profiles.work]\n").unwrap();
+
+        crate::config::switch_profile(client, &file_config, "work")
+            .await
+            .unwrap();
+        assert_eq!(
+            crate::config::active_profile(client, &file_config)
+                .await
+                .unwrap(),
+            "work"
+        );
+
+        let unknown = crate::config::switch_profile(client, &file_config, "unknown")
+            .await
+            .unwrap_err();
+        assert_eq!(unknown.code(), Code::NotFound);
+        assert!(unknown.message().contains("unknown"));
+    }
+}
@@ -0,0 +1,437 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use reqwest::{header, StatusCode};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Number of times a download is attempted before giving up
+const MAX_ATTEMPTS: u32 = 5;
+/// Delay before the first retry, doubled for each further attempt
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// A file to fetch over HTTP
+pub struct Download {
+    pub url: reqwest::Url,
+    /// `Cookie` header forwarded by the extension
+    pub cookie: Option<String>,
+    /// Expected SHA-256 of the content, hex encoded
+    pub sha256: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum DownloadError {
+    /// The server answered with an error status
+    Status(StatusCode),
+    /// The server resumed from a different offset than requested
+    UnexpectedRange,
+    Http(reqwest::Error),
+    Io(std::io::Error),
+    ChecksumMismatch {
+        expected: String,
+        actual: String,
+    },
+}
+
+impl DownloadError {
+    fn is_retryable(&self) -> bool {
+        match self {
+            DownloadError::Status(status) => {
+                status.is_server_error()
+                    || *status == StatusCode::TOO_MANY_REQUESTS
+                    || *status == StatusCode::REQUEST_TIMEOUT
+            }
+            DownloadError::UnexpectedRange => true,
+            DownloadError::Http(err) => !err.is_builder() && !err.is_redirect(),
+            DownloadError::Io(_) | DownloadError::ChecksumMismatch { .. } => false,
+        }
+    }
+}
+
+impl std::fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DownloadError::Status(status) => write!(f, "Server responded with {}", status),
+            DownloadError::UnexpectedRange => write!(f, "Server resumed at the wrong offset"),
+            DownloadError::Http(err) => write!(f, "{}", err),
+            DownloadError::Io(err) => write!(f, "{}", err),
+            DownloadError::ChecksumMismatch { expected, actual } => {
+                write!(f, "Expected SHA-256 {} but got {}", expected, actual)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {}
+
+impl From<reqwest::Error> for DownloadError {
+    fn from(err: reqwest::Error) -> Self {
+        DownloadError::Http(err)
+    }
+}
+
+impl From<std::io::Error> for DownloadError {
+    fn from(err: std::io::Error) -> Self {
+        DownloadError::Io(err)
+    }
+}
+
+/// Downloads into `partial`, resuming from whatever it already contains as long as the file
+/// on the server has not changed since.
+///
+/// Returns the size of the complete file once its checksum has been verified.
+/// On failure `partial` is kept, so that the next call can resume it, unless the
+/// checksum did not match.
+pub async fn fetch(
+    client: &reqwest::Client,
+    download: &Download,
+    partial: &Path,
+) -> Result<u64, DownloadError> {
+    let mut attempt = 0;
+    loop {
+        match fetch_once(client, download, partial).await {
+            Ok(()) => break,
+            Err(err) if err.is_retryable() && attempt + 1 < MAX_ATTEMPTS => {
+                tokio::time::sleep(RETRY_DELAY * 2u32.pow(attempt)).await;
+                attempt += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    let (size, actual) = hash_file(partial).await?;
+    if let Some(ref expected) = download.sha256 {
+        if !expected.eq_ignore_ascii_case(&actual) {
+            discard(partial).await?;
+            return Err(DownloadError::ChecksumMismatch {
+                expected: expected.to_owned(),
+                actual,
+            });
+        }
+    }
+    let _ = tokio::fs::remove_file(validator_path(partial)).await;
+
+    Ok(size)
+}
+
+/// Deletes `partial` along with what is kept to resume it.
+pub async fn discard(partial: &Path) -> std::io::Result<()> {
+    let _ = tokio::fs::remove_file(validator_path(partial)).await;
+    tokio::fs::remove_file(partial).await
+}
+
+/// File next to `partial` that holds the `ETag` or `Last-Modified` of the file being downloaded
+fn validator_path(partial: &Path) -> PathBuf {
+    let mut path = partial.as_os_str().to_owned();
+    path.push(".validator");
+    PathBuf::from(path)
+}
+
+async fn fetch_once(
+    client: &reqwest::Client,
+    download: &Download,
+    partial: &Path,
+) -> Result<(), DownloadError> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(partial)
+        .await?;
+    let offset = file.metadata().await?.len();
+    let validator_path = validator_path(partial);
+
+    let mut request = client.get(download.url.to_owned());
+    if let Some(ref cookie) = download.cookie {
+        request = request.header(header::COOKIE, cookie);
+    }
+    if offset > 0 {
+        request = request.header(header::RANGE, format!("bytes={}-", offset));
+        // The server sends the whole file instead if it has changed since
+        if let Ok(validator) = tokio::fs::read_to_string(&validator_path).await {
+            request = request.header(header::IF_RANGE, validator);
+        }
+    }
+    let mut response = request.send().await?;
+
+    match response.status() {
+        StatusCode::PARTIAL_CONTENT if offset > 0 => {
+            let resumes_at_offset = response
+                .headers()
+                .get(header::CONTENT_RANGE)
+                .and_then(|range| range.to_str().ok())
+                .map_or(false, |range| {
+                    range.starts_with(&format!("bytes {}-", offset))
+                });
+            if !resumes_at_offset {
+                file.set_len(0).await?;
+                return Err(DownloadError::UnexpectedRange);
+            }
+        }
+        StatusCode::RANGE_NOT_SATISFIABLE if offset > 0 => {
+            // Only complete if the server has exactly as many bytes as are on disk
+            let total = response
+                .headers()
+                .get(header::CONTENT_RANGE)
+                .and_then(|range| range.to_str().ok())
+                .and_then(|range| range.strip_prefix("bytes */"))
+                .and_then(|total| total.trim().parse::<u64>().ok());
+            if total == Some(offset) {
+                return Ok(());
+            }
+            file.set_len(0).await?;
+            return Err(DownloadError::UnexpectedRange);
+        }
+        // The server ignored the range or the file has changed, so start over
+        status if status.is_success() => {
+            file.set_len(0).await?;
+            store_validator(response.headers(), &validator_path).await?;
+        }
+        status => return Err(DownloadError::Status(status)),
+    }
+
+    while let Some(chunk) = response.chunk().await? {
+        file.write_all(&chunk).await?;
+    }
+    file.sync_all().await?;
+
+    Ok(())
+}
+
+/// Keeps what identifies the version of the file that is being downloaded, for `If-Range`.
+///
+/// Weak `ETag`s are not allowed there, so `Last-Modified` is used instead.
+async fn store_validator(headers: &header::HeaderMap, path: &Path) -> std::io::Result<()> {
+    let etag = headers
+        .get(header::ETAG)
+        .filter(|etag| !etag.as_bytes().starts_with(b"W/"));
+    match etag.or_else(|| headers.get(header::LAST_MODIFIED)) {
+        Some(validator) => tokio::fs::write(path, validator.as_bytes()).await,
+        None => match tokio::fs::remove_file(path).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        },
+    }
+}
+
+async fn hash_file(path: &Path) -> std::io::Result<(u64, String)> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let len = file.read(&mut buf).await?;
+        if len == 0 {
+            break;
+        }
+        hasher.update(&buf[..len]);
+        size += len as u64;
+    }
+
+    let hash = hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    Ok((size, hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use crate::downloader::{fetch, validator_path, Download, DownloadError};
+
+    const CONTENT: &[u8] = b"0123456789abcdefghijklmnopqrstuvwxyz";
+    const CONTENT_SHA256: &str = "74e7e5bb9d22d6db26bf76946d40fff3ea9f0346b884fd0694920fccfad15e33";
+
+    /// Serves `CONTENT`, honouring `Range: bytes=<start>-`. The first response is cut off after 10 bytes.
+    async fn serve(listener: TcpListener) {
+        let mut first = true;
+        loop {
+            let Ok((mut socket, _)) = listener.accept().await else {
+                return;
+            };
+            let mut buf = vec![0u8; 4096];
+            let len = socket.read(&mut buf).await.unwrap();
+            let request = String::from_utf8_lossy(&buf[..len]).to_lowercase();
+            let start = request
+                .lines()
+                .find_map(|line| line.strip_prefix("range: bytes="))
+                .and_then(|range| range.trim_end_matches('-').parse::<usize>().ok());
+
+            let response = match start {
+                Some(start) => format!(
+                    "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                    CONTENT.len() - start,
+                    start,
+                    CONTENT.len() - 1,
+                    CONTENT.len()
+                ),
+                None => format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n",
+                    CONTENT.len()
+                ),
+            };
+            socket.write_all(response.as_bytes()).await.unwrap();
+            let body = &CONTENT[start.unwrap_or(0)..];
+            if first {
+                first = false;
+                socket.write_all(&body[..10]).await.unwrap();
+            } else {
+                socket.write_all(body).await.unwrap();
+            }
+        }
+    }
+
+    /// Serves `CONTENT` with the ETag `"v2"`, honouring a range only if `If-Range` matches it.
+    async fn serve_versioned(listener: TcpListener) {
+        loop {
+            let Ok((mut socket, _)) = listener.accept().await else {
+                return;
+            };
+            let mut buf = vec![0u8; 4096];
+            let len = socket.read(&mut buf).await.unwrap();
+            let request = String::from_utf8_lossy(&buf[..len]).to_lowercase();
+            let header = |name: &str| {
+                request
+                    .lines()
+                    .find_map(|line| line.strip_prefix(name))
+                    .map(|value| value.trim().to_owned())
+            };
+            let start = header("range: bytes=")
+                .and_then(|range| range.trim_end_matches('-').parse::<usize>().ok())
+                .filter(|_| header("if-range:").map_or(true, |tag| tag == "\"v2\""));
+
+            let (head, body) = match start {
+                Some(start) if start >= CONTENT.len() => (
+                    format!(
+                        "416 Range Not Satisfiable\r\nContent-Range: bytes */{}\r\nContent-Length: 0",
+                        CONTENT.len()
+                    ),
+                    &[][..],
+                ),
+                Some(start) => (
+                    format!(
+                        "206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {}-{}/{}",
+                        CONTENT.len() - start,
+                        start,
+                        CONTENT.len() - 1,
+                        CONTENT.len()
+                    ),
+                    &CONTENT[start..],
+                ),
+                None => (
+                    format!(
+                        "200 OK\r\nContent-Length: {}\r\nETag: \"v2\"",
+                        CONTENT.len()
+                    ),
+                    CONTENT,
+                ),
+            };
+            let head = format!("HTTP/1.1 {}\r\n\r\n", head);
+            socket.write_all(head.as_bytes()).await.unwrap();
+            socket.write_all(body).await.unwrap();
+        }
+    }
+
+    async fn start_server() -> reqwest::Url {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/file", listener.local_addr().unwrap());
+        tokio::spawn(serve(listener));
+        reqwest::Url::parse(&url).unwrap()
+    }
+
+    /// Fetches from a versioned server into a partial file that holds `content` of `version`.
+    async fn fetch_versioned(
+        name: &str,
+        content: &[u8],
+        version: &str,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = reqwest::Url::parse(&format!("http://{}/file", listener.local_addr()?))?;
+        tokio::spawn(serve_versioned(listener));
+        let partial =
+            std::env::temp_dir().join(format!("edman-download-{}.{}", std::process::id(), name));
+        std::fs::write(&partial, content)?;
+        std::fs::write(validator_path(&partial), version)?;
+        let download = Download {
+            url,
+            cookie: None,
+            sha256: Some(CONTENT_SHA256.to_string()),
+        };
+
+        let size = fetch(&reqwest::Client::new(), &download, &partial).await?;
+
+        assert_eq!(size, CONTENT.len() as u64);
+        assert!(!validator_path(&partial).exists());
+        let fetched = std::fs::read(&partial)?;
+        std::fs::remove_file(partial)?;
+        Ok(fetched)
+    }
+
+    #[tokio::test]
+    async fn resume_after_interruption() -> Result<(), Box<dyn std::error::Error>> {
+        let url = start_server().await;
+        let partial =
+            std::env::temp_dir().join(format!("edman-download-{}.part", std::process::id()));
+        let download = Download {
+            url,
+            cookie: None,
+            sha256: Some(CONTENT_SHA256.to_string()),
+        };
+
+        let size = fetch(&reqwest::Client::new(), &download, &partial).await?;
+
+        assert_eq!(size, CONTENT.len() as u64);
+        assert_eq!(std::fs::read(&partial)?, CONTENT);
+        std::fs::remove_file(partial)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn checksum_mismatch() {
+        let url = start_server().await;
+        let partial =
+            std::env::temp_dir().join(format!("edman-download-{}.bad", std::process::id()));
+        let download = Download {
+            url,
+            cookie: None,
+            sha256: Some("00".repeat(32)),
+        };
+
+        let result = fetch(&reqwest::Client::new(), &download, &partial).await;
+
+        assert!(matches!(
+            result,
+            Err(DownloadError::ChecksumMismatch { .. })
+        ));
+        assert!(!partial.exists());
+    }
+
+    #[tokio::test]
+    async fn changed_file_is_downloaded_again() -> Result<(), Box<dyn std::error::Error>> {
+        let fetched = fetch_versioned("changed", b"0123456789", "\"v1\"").await?;
+
+        assert_eq!(fetched, CONTENT);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn complete_file_is_not_downloaded_again() -> Result<(), Box<dyn std::error::Error>> {
+        let fetched = fetch_versioned("complete", CONTENT, "\"v2\"").await?;
+
+        assert_eq!(fetched, CONTENT);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn file_longer_than_on_the_server_is_downloaded_again(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let content = [CONTENT, b"extra"].concat();
+        let fetched = fetch_versioned("longer", &content, "\"v2\"").await?;
+
+        assert_eq!(fetched, CONTENT);
+        Ok(())
+    }
+}
@@ -0,0 +1,27 @@
+use tokio::sync::broadcast;
+
+use crate::grpc::ui;
+
+/// Fans out events to every `WatchEvents` subscriber.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<ui::Event>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(64);
+        Self { sender }
+    }
+}
+
+impl EventBus {
+    pub fn emit(&self, kind: ui::event::Kind) {
+        // Nobody may be listening, which is fine
+        let _ = self.sender.send(ui::Event { kind: Some(kind) });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ui::Event> {
+        self.sender.subscribe()
+    }
+}
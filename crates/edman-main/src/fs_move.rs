@@ -0,0 +1,182 @@
+use std::{
+    ffi::OsString,
+    fs::File,
+    io::{ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+cfg_if::cfg_if! {
+    if #[cfg(windows)] {
+        /// What renaming fails with if the target is on another volume
+        const CROSS_DEVICE_ERROR: i32 =
+            windows_sys::Win32::Foundation::ERROR_NOT_SAME_DEVICE as i32;
+    } else {
+        /// What renaming fails with if the target is on another file system
+        const CROSS_DEVICE_ERROR: i32 = libc::EXDEV;
+    }
+}
+
+/// Moves `source` to `target`, even if they are on different file systems.
+///
+/// A cross-device move copies into a temporary file next to `target`, so the
+/// destination never holds a half-written file, and only deletes `source` after
+/// the copy has been verified.
+pub fn move_file(source: &Path, target: &Path) -> std::io::Result<()> {
+    match std::fs::rename(source, target) {
+        Err(err) if err.raw_os_error() == Some(CROSS_DEVICE_ERROR) => {
+            copy_verified(source, target)?;
+            std::fs::remove_file(source)
+        }
+        result => result,
+    }
+}
+
+fn copy_verified(source: &Path, target: &Path) -> std::io::Result<()> {
+    let temp_path = temp_path(target);
+    // Left over from an interrupted move
+    if temp_path.exists() {
+        std::fs::remove_file(&temp_path)?;
+    }
+
+    let result = copy_to_temp(source, &temp_path).and_then(|()| {
+        std::fs::rename(&temp_path, target)?;
+        sync_parent(target)
+    });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result
+}
+
+fn copy_to_temp(source: &Path, temp_path: &Path) -> std::io::Result<()> {
+    let metadata = std::fs::metadata(source)?;
+
+    let mut reader = File::open(source)?;
+    let mut writer = File::options()
+        .write(true)
+        .create_new(true)
+        .open(temp_path)?;
+
+    let mut source_hash = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let len = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(len) => len,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        source_hash.update(&buf[..len]);
+        writer.write_all(&buf[..len])?;
+    }
+    // Before syncing, so that the metadata is as durable as the contents
+    filetime::set_file_handle_times(
+        &writer,
+        None,
+        Some(filetime::FileTime::from_last_modification_time(&metadata)),
+    )?;
+    writer.set_permissions(metadata.permissions())?;
+    writer.sync_all()?;
+    drop(writer);
+
+    // Read the copy back, so that what ends up on disk is what gets compared
+    let mut copy_hash = Sha256::new();
+    let copied_len = std::io::copy(&mut File::open(temp_path)?, &mut copy_hash)?;
+    if copied_len != metadata.len() || copy_hash.finalize() != source_hash.finalize() {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Copy of \"{}\" does not match the original",
+                source.display()
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// `.<name>.edman-tmp` in the same directory as `target`
+fn temp_path(target: &Path) -> PathBuf {
+    let mut file_name = OsString::from(".");
+    if let Some(name) = target.file_name() {
+        file_name.push(name);
+    }
+    file_name.push(".edman-tmp");
+    target.with_file_name(file_name)
+}
+
+/// Makes the rename into the parent directory durable.
+fn sync_parent(path: &Path) -> std::io::Result<()> {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            match path.parent() {
+                Some(parent) => File::open(parent)?.sync_all(),
+                None => Ok(()),
+            }
+        } else {
+            // Directories cannot be opened as files on Windows
+            let _ = path;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fs_move::{copy_verified, temp_path};
+
+    #[test]
+    fn copy_keeps_metadata() -> std::io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("edman-fs-move-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let source = dir.join("source.bin");
+        let target = dir.join("target.bin");
+        std::fs::write(&source, vec![7u8; 200 * 1024])?;
+        let mtime = filetime::FileTime::from_unix_time(1_600_000_000, 0);
+        filetime::set_file_mtime(&source, mtime)?;
+
+        copy_verified(&source, &target)?;
+
+        assert_eq!(std::fs::read(&target)?, std::fs::read(&source)?);
+        assert_eq!(
+            filetime::FileTime::from_last_modification_time(&std::fs::metadata(&target)?),
+            mtime
+        );
+        assert!(!temp_path(&target).exists());
+
+        std::fs::remove_dir_all(dir)
+    }
+
+    #[test]
+    fn copy_replaces_leftovers() -> std::io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("edman-fs-leftovers-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let source = dir.join("source.txt");
+        let target = dir.join("target.txt");
+        std::fs::write(&source, "new")?;
+        std::fs::write(&target, "old")?;
+        // As if an earlier move had been interrupted
+        std::fs::write(temp_path(&target), "partial")?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&source, std::fs::Permissions::from_mode(0o640))?;
+        }
+
+        copy_verified(&source, &target)?;
+
+        assert_eq!(std::fs::read_to_string(&target)?, "new");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&target)?.permissions().mode();
+            assert_eq!(mode & 0o777, 0o640);
+        }
+        assert!(!temp_path(&target).exists());
+        assert!(source.exists());
+
+        std::fs::remove_dir_all(dir)
+    }
+}
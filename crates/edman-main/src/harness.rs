@@ -0,0 +1,97 @@
+use std::io::ErrorKind;
+use std::sync::Arc;
+
+use tokio::io::DuplexStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::{Channel, Endpoint, Server, Uri};
+
+use crate::config::file::FileConfig;
+use crate::grpc::{
+    chrome_extension::download_manager_client::DownloadManagerClient,
+    ui::edman_main_client::EdmanMainClient,
+};
+use crate::queue::Limits;
+use crate::temp_db::TempDatabase;
+
+/// Buffer of each in-memory connection
+const PIPE_SIZE: usize = 1024 * 1024;
+
+/// edman-main running inside a test process, with a database of its own.
+///
+/// Clients talk to it over in-memory pipes instead of the socket, so several
+/// harnesses can run at the same time and next to a real edman-main.
+/// The services stop and the database is deleted when it is dropped.
+pub struct Harness {
+    channel: Channel,
+    _database: TempDatabase,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl Harness {
+    pub async fn start() -> Result<Self, Box<dyn std::error::Error>> {
+        let database = TempDatabase::create().await?;
+        let prisma_client = database.client.to_owned();
+
+        let limits = Limits {
+            global: 4,
+            per_host: 2,
+        };
+        let server = crate::services(prisma_client, Arc::new(FileConfig::default()), limits)
+            .await?
+            .routes(&mut Server::builder());
+
+        // Each connection the channel opens is served over a pipe of its own
+        let (connection_tx, connection_rx) = mpsc::channel::<std::io::Result<DuplexStream>>(4);
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        tokio::spawn(server.serve_with_incoming_shutdown(
+            ReceiverStream::new(connection_rx),
+            async {
+                shutdown_rx.await.ok();
+            },
+        ));
+
+        // The URI is never resolved, but an endpoint needs one
+        let channel = Endpoint::from_static("http://edman-main.harness")
+            .connect_with_connector(tower::service_fn(move |_: Uri| {
+                let connection_tx = connection_tx.clone();
+                async move {
+                    let (client, server) = tokio::io::duplex(PIPE_SIZE);
+                    connection_tx.send(Ok(server)).await.map_err(|_| {
+                        std::io::Error::new(ErrorKind::BrokenPipe, "The harness has stopped")
+                    })?;
+                    Ok::<_, std::io::Error>(client)
+                }
+            }))
+            .await?;
+
+        Ok(Self {
+            channel,
+            _database: database,
+            shutdown: Some(shutdown),
+        })
+    }
+
+    /// Channel to both services, for crates that generate clients of their own
+    pub fn channel(&self) -> Channel {
+        self.channel.clone()
+    }
+
+    /// Client of the service that edman-ce-adapter talks to
+    pub fn download_manager(&self) -> DownloadManagerClient<Channel> {
+        DownloadManagerClient::new(self.channel())
+    }
+
+    /// Client of the service that edman-ui talks to
+    pub fn edman_main(&self) -> EdmanMainClient<Channel> {
+        EdmanMainClient::new(self.channel())
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
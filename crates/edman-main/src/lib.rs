@@ -0,0 +1,218 @@
+use std::sync::Arc;
+
+use clap::Parser;
+use prisma_codegen::PrismaClient;
+
+use tonic::{
+    transport::{server::Router, Server},
+    Status,
+};
+use transport::tcp::{RequireToken, TcpOptions};
+
+use crate::cli::{Cli, Command};
+use crate::config::file::FileConfig;
+use crate::grpc::{
+    chrome_extension::download_manager_server::DownloadManagerServer,
+    ui::edman_main_server::EdmanMainServer,
+};
+
+mod ce_adapter;
+mod cli;
+mod collision;
+mod config;
+mod downloader;
+mod events;
+mod fs_move;
+pub mod grpc;
+#[cfg(feature = "harness")]
+pub mod harness;
+mod manifests;
+mod progress;
+mod queue;
+mod quota;
+mod storage;
+#[cfg(any(test, feature = "harness"))]
+mod temp_db;
+mod ui;
+
+pub fn error_prisma_to_tonic(err: prisma_client_rust::QueryError) -> Status {
+    use prisma_client_rust::prisma_errors::query_engine::*;
+    if err.is_prisma_error::<PoolTimeout>() {
+        Status::unavailable("Database timeout")
+    } else if err.is_prisma_error::<UniqueKeyViolation>() {
+        Status::already_exists("Unique key violation")
+    } else if err.is_prisma_error::<RecordNotFound>() {
+        Status::not_found("Record not found")
+    } else if err.is_prisma_error::<ForeignKeyViolation>() {
+        Status::failed_precondition("Foreign key violation")
+    } else {
+        Status::internal(format!("Database error: {:?}", err))
+    }
+}
+
+pub fn error_io_to_tonic(err: std::io::Error) -> Status {
+    use std::io::ErrorKind;
+    match err.kind() {
+        ErrorKind::NotFound => Status::not_found(err.to_string()),
+        ErrorKind::AlreadyExists => Status::already_exists(err.to_string()),
+        ErrorKind::PermissionDenied => Status::permission_denied(err.to_string()),
+        ErrorKind::InvalidInput => Status::invalid_argument(err.to_string()),
+        _ => Status::internal(format!("IO error: {}", err)),
+    }
+}
+
+/// Runs edman-main with the command line arguments of the process.
+pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+
+    let prisma_client = Arc::new(PrismaClient::_builder().build().await?);
+    prisma_client._migrate_deploy().await?;
+
+    let file_config = Arc::new(FileConfig::load(utils::config_file_path())?);
+
+    match cli.command {
+        Some(Command::Config(command)) => {
+            cli::run_config_command(command, &prisma_client, &file_config).await
+        }
+        None => {
+            let limits = queue::Limits {
+                global: cli.max_downloads,
+                per_host: cli.max_downloads_per_host,
+            };
+            serve(prisma_client, file_config, limits, cli.tcp_options()).await
+        }
+    }
+}
+
+async fn serve(
+    prisma_client: Arc<PrismaClient>,
+    file_config: Arc<FileConfig>,
+    limits: queue::Limits,
+    tcp: Option<TcpOptions>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !file_config.is_empty() {
+        println!("Using {}", utils::config_file_path().display());
+    }
+
+    let services = services(prisma_client, file_config, limits).await?;
+    // Set up before anything listens, so that a bad certificate or token stops edman-main
+    let tcp_server = match tcp {
+        Some(options) => {
+            let require_token = tonic::service::interceptor(RequireToken::new(&options.token)?);
+            let mut server = options.tls_server()?.layer(require_token);
+            Some((services.to_owned().remote_routes(&mut server), options.addr))
+        }
+        None => None,
+    };
+    let stream = transport::sock_stream().await?;
+
+    let (tx, rx) = tokio::sync::watch::channel(());
+    let mut server_threads = vec![];
+    let server = services.routes(&mut Server::builder());
+    let shutdown = shutdown_signal(rx.to_owned());
+    server_threads.push(tokio::spawn(async move {
+        if let Err(e) = server.serve_with_incoming_shutdown(stream, shutdown).await {
+            eprintln!("server error: {}", e);
+        }
+    }));
+    if let Some((server, addr)) = tcp_server {
+        println!("Listening at {} (TCP)", addr);
+        let shutdown = shutdown_signal(rx.to_owned());
+        server_threads.push(tokio::spawn(async move {
+            if let Err(e) = server.serve_with_shutdown(addr, shutdown).await {
+                eprintln!("TCP server error: {}", e);
+            }
+        }));
+    }
+
+    match tokio::signal::ctrl_c().await {
+        Ok(()) => {}
+        Err(err) => {
+            eprintln!("Unable to listen for shutdown signal: {}", err);
+        }
+    }
+
+    let _ = tx.send(());
+    for server_thread in server_threads {
+        server_thread.await?;
+    }
+
+    transport::dispose_socket().await?;
+
+    Ok(())
+}
+
+/// Resolves once `serve` tells the servers to stop
+async fn shutdown_signal(mut rx: tokio::sync::watch::Receiver<()>) {
+    rx.changed().await.ok();
+}
+
+/// Every service of edman-main, which may be served on several listeners at once
+#[derive(Clone)]
+struct Services {
+    download_manager: DownloadManagerServer<ce_adapter::ChromeExtensionInterface>,
+    edman_main: EdmanMainServer<ui::UiInterface>,
+    /// Same as `edman_main`, but refuses to set directories
+    remote_edman_main: EdmanMainServer<ui::UiInterface>,
+}
+
+impl Services {
+    fn routes<L: Clone>(self, server: &mut Server<L>) -> Router<L> {
+        server
+            .add_service(self.download_manager)
+            .add_service(self.edman_main)
+    }
+
+    /// Routes for clients on other machines.
+    ///
+    /// Only edman-ui connects remotely. The service of edman-ce-adapter is left out, since its
+    /// requests name files on this machine, e.g. the download to commit or the manifest to
+    /// rewrite, and edman-ce-adapter always runs next to edman-main anyway.
+    fn remote_routes<L: Clone>(self, server: &mut Server<L>) -> Router<L> {
+        server.add_service(self.remote_edman_main)
+    }
+}
+
+/// Sets up every service of edman-main on `prisma_client`, which has to be migrated.
+async fn services(
+    prisma_client: Arc<PrismaClient>,
+    file_config: Arc<FileConfig>,
+    limits: queue::Limits,
+) -> Result<Services, Box<dyn std::error::Error>> {
+    let events = events::EventBus::default();
+    let store = storage::FileStore::new(prisma_client.to_owned(), events.to_owned());
+    let queue = queue::Queue::new(
+        prisma_client.to_owned(),
+        file_config.to_owned(),
+        store.to_owned(),
+        limits,
+    );
+    queue.start().await?;
+    let progress = progress::ProgressTracker::default();
+
+    let ce_adapter_interface = ce_adapter::ChromeExtensionInterface {
+        prisma_client: prisma_client.to_owned(),
+        file_config: file_config.to_owned(),
+        store,
+        queue: queue.to_owned(),
+        progress: progress.to_owned(),
+    };
+    let ui_interface = ui::UiInterface {
+        prisma_client: prisma_client.to_owned(),
+        file_config: file_config.to_owned(),
+        events,
+        queue,
+        progress,
+        remote: false,
+    };
+    let remote_ui_interface = ui::UiInterface {
+        remote: true,
+        ..ui_interface.clone()
+    };
+
+    Ok(Services {
+        download_manager: DownloadManagerServer::new(ce_adapter_interface),
+        edman_main: EdmanMainServer::new(ui_interface),
+        remote_edman_main: EdmanMainServer::new(remote_ui_interface),
+    })
+}
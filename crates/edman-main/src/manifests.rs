@@ -0,0 +1,207 @@
+use std::io::ErrorKind;
+use std::path::Path;
+
+use prisma_client_rust::QueryError;
+use prisma_codegen::{installed_manifest, PrismaClient};
+use utils::browsers::{merge_allow_lists, BrowserKind, BrowserStrain};
+
+use crate::grpc::{chrome_extension::ManifestKind, config::Profile, ui};
+
+/// Remembers a manifest that edman-ce-adapter installed for `browser`.
+pub async fn register(
+    client: &PrismaClient,
+    browser: String,
+    path: String,
+    kind: ManifestKind,
+) -> Result<(), QueryError> {
+    client
+        .installed_manifest()
+        .upsert(
+            installed_manifest::UniqueWhereParam::BrowserPathEquals(
+                browser.to_owned(),
+                path.to_owned(),
+            ),
+            installed_manifest::create(
+                browser,
+                path,
+                vec![installed_manifest::SetParam::SetKind(kind as i32)],
+            ),
+            vec![installed_manifest::SetParam::SetKind(kind as i32)],
+        )
+        .exec()
+        .await?;
+    Ok(())
+}
+
+/// Allow-lists of every profile combined, so that any of them can launch the host.
+///
+/// Same as what edman-ce-adapter puts into a manifest when installing it.
+pub fn allow_lists(profiles: &[Profile]) -> (Vec<String>, Vec<String>) {
+    let configs: Vec<_> = profiles
+        .iter()
+        .filter_map(|profile| profile.config.as_ref())
+        .collect();
+    (
+        merge_allow_lists(configs.iter().map(|config| &config.allowed_origins[..])),
+        merge_allow_lists(configs.iter().map(|config| &config.allowed_extensions[..])),
+    )
+}
+
+/// Whether edman-ce-adapter may have installed a manifest of `kind` at `path` for `browser`.
+///
+/// Anything else is refused, so that registering cannot make edman-main write to arbitrary files.
+pub fn is_manifest_file(browser: &str, path: &Path, kind: ManifestKind) -> bool {
+    let Some(browser) = BrowserKind::from_name(browser) else {
+        return false;
+    };
+    let strain = match kind {
+        ManifestKind::Chromium => BrowserStrain::Chromium,
+        ManifestKind::Firefox => BrowserStrain::Firefox,
+    };
+    BrowserStrain::from(&browser) == strain && utils::locations::is_manifest_file(&browser, path)
+}
+
+/// Writes the allow-lists of `profiles` into every installed manifest.
+///
+/// Manifests that no longer exist, e.g. because they were uninstalled, are forgotten, and so
+/// are those that are not at a location of their browser or no longer belong to edman.
+pub async fn rewrite_all(
+    client: &PrismaClient,
+    profiles: &[Profile],
+) -> Result<Vec<ui::ManifestUpdate>, QueryError> {
+    let (origins, extensions) = allow_lists(profiles);
+    let records = client.installed_manifest().find_many(vec![]).exec().await?;
+
+    let mut updates = vec![];
+    for record in records {
+        let kind = ManifestKind::try_from(record.kind).unwrap_or(ManifestKind::Chromium);
+        let (key, list) = match kind {
+            ManifestKind::Firefox => ("allowed_extensions", &extensions),
+            ManifestKind::Chromium => ("allowed_origins", &origins),
+        };
+        let path = Path::new(&record.path);
+        let rewritten = match is_manifest_file(&record.browser, path, kind) {
+            true => rewrite(path, key, list),
+            false => Ok(false),
+        };
+        let error = match rewritten {
+            Ok(true) => None,
+            Ok(false) => {
+                client
+                    .installed_manifest()
+                    .delete(installed_manifest::UniqueWhereParam::IdEquals(record.id))
+                    .exec()
+                    .await?;
+                continue;
+            }
+            Err(err) => Some(err.to_string()),
+        };
+        updates.push(ui::ManifestUpdate {
+            browser: record.browser,
+            path: record.path,
+            error,
+        });
+    }
+    Ok(updates)
+}
+
+/// Replaces the allow-list `key` of the manifest at `path`, keeping everything else.
+///
+/// Returns `false` if there is no manifest of edman at `path`. The file is replaced at once,
+/// so that a browser never reads a half-written manifest.
+fn rewrite(path: &Path, key: &str, list: &[String]) -> std::io::Result<bool> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err),
+    };
+
+    let mut manifest: serde_json::Value = serde_json::from_str(&content)?;
+    let Some(fields) = manifest.as_object_mut() else {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            "The manifest is not a JSON object",
+        ));
+    };
+    let is_edman = fields.get("name").and_then(|name| name.as_str())
+        == Some(utils::EDMAN_UNIQUE_NAME)
+        && fields.get("type").and_then(|kind| kind.as_str()) == Some("stdio");
+    if !is_edman {
+        return Ok(false);
+    }
+    fields.insert(key.to_owned(), serde_json::json!(list));
+
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{}.edman-tmp", file_name));
+    std::fs::write(&temp, serde_json::to_string_pretty(&manifest)?)?;
+    if let Err(err) = std::fs::rename(&temp, path) {
+        let _ = std::fs::remove_file(&temp);
+        return Err(err);
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::grpc::chrome_extension::ManifestKind;
+    use crate::manifests::{is_manifest_file, rewrite};
+
+    #[test]
+    fn rewrite_keeps_other_fields() {
+        let path = std::env::temp_dir().join(format!("edman-manifest-{}.json", std::process::id()));
+        let manifest = serde_json::json!({
+            "name": "io.github.femshima.edman",
+            "path": "/opt/edman/edman-ce-adapter",
+            "type": "stdio",
+            "allowed_origins": ["chrome-extension://old/"],
+        });
+        std::fs::write(&path, manifest.to_string()).unwrap();
+
+        let origins = vec!["chrome-extension://a/".to_string()];
+        assert!(rewrite(&path, "allowed_origins", &origins).unwrap());
+
+        let rewritten: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(rewritten["path"], manifest["path"]);
+        assert_eq!(rewritten["allowed_origins"], serde_json::json!(origins));
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(!rewrite(&path, "allowed_origins", &origins).unwrap());
+    }
+
+    #[test]
+    fn foreign_manifest_is_left_alone() {
+        let path = std::env::temp_dir().join(format!(
+            "edman-foreign-manifest-{}.json",
+            std::process::id()
+        ));
+        let foreign = serde_json::json!({
+            "name": "com.example.other",
+            "path": "/usr/bin/other-host",
+            "type": "stdio",
+            "allowed_origins": ["chrome-extension://other/"],
+        })
+        .to_string();
+        std::fs::write(&path, &foreign).unwrap();
+
+        let origins = vec!["chrome-extension://a/".to_string()];
+        assert!(!rewrite(&path, "allowed_origins", &origins).unwrap());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), foreign);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn only_manifest_locations_are_accepted() {
+        let path = utils::manifest_path_chromium();
+        assert!(is_manifest_file("chrome", &path, ManifestKind::Chromium));
+        assert!(!is_manifest_file("chrome", &path, ManifestKind::Firefox));
+        assert!(!is_manifest_file("firefox", &path, ManifestKind::Firefox));
+        assert!(!is_manifest_file("unknown", &path, ManifestKind::Chromium));
+        assert!(!is_manifest_file(
+            "chrome",
+            &std::env::temp_dir().join("manifest.json"),
+            ManifestKind::Chromium
+        ));
+    }
+}
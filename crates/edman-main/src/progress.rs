@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::grpc::ui::{self, ProgressState};
+
+/// How long a download is kept after its last report. The extension reports downloads in
+/// progress every second, so one that is not reported for this long belongs to a browser
+/// that has been closed.
+const EXPIRY: Duration = Duration::from_secs(60);
+
+/// Downloads that the browsers are fetching, as reported by the extensions.
+///
+/// Only kept in memory: the extension reports everything again after a restart.
+/// Finished downloads are kept until they expire, for subscribers that fall behind.
+#[derive(Clone)]
+pub struct ProgressTracker {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    /// Keyed by origin and the id the browser assigned to the download
+    downloads: Mutex<HashMap<(String, i32), Reported>>,
+    sender: broadcast::Sender<ui::DownloadProgress>,
+}
+
+struct Reported {
+    progress: ui::DownloadProgress,
+    at: Instant,
+}
+
+impl Default for ProgressTracker {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(256);
+        Self {
+            inner: Arc::new(Inner {
+                downloads: Default::default(),
+                sender,
+            }),
+        }
+    }
+}
+
+impl ProgressTracker {
+    pub fn report(&self, progress: ui::DownloadProgress) {
+        self.report_at(progress, Instant::now());
+    }
+
+    fn report_at(&self, progress: ui::DownloadProgress, now: Instant) {
+        let mut downloads = self.inner.downloads.lock().unwrap();
+        let key = (progress.origin.to_owned(), progress.download_id);
+        downloads.insert(
+            key,
+            Reported {
+                progress: progress.clone(),
+                at: now,
+            },
+        );
+
+        // Nobody may be listening, which is fine
+        let _ = self.inner.sender.send(progress);
+        self.expire(&mut downloads, now);
+    }
+
+    /// Drops the downloads that have not been reported for [`EXPIRY`], reporting those still
+    /// in progress as interrupted.
+    fn expire(&self, downloads: &mut HashMap<(String, i32), Reported>, now: Instant) {
+        downloads.retain(|_, reported| {
+            if now.saturating_duration_since(reported.at) < EXPIRY {
+                return true;
+            }
+            if reported.progress.state == ProgressState::InProgress as i32 {
+                let _ = self.inner.sender.send(ui::DownloadProgress {
+                    state: ProgressState::Interrupted as i32,
+                    error: Some("The browser stopped reporting the download".to_owned()),
+                    ..reported.progress.clone()
+                });
+            }
+            false
+        });
+    }
+
+    /// Returns the downloads in flight and a receiver for every later update.
+    pub fn subscribe(
+        &self,
+    ) -> (
+        Vec<ui::DownloadProgress>,
+        broadcast::Receiver<ui::DownloadProgress>,
+    ) {
+        // Subscribe under the lock, so that no update falls between the two
+        let mut downloads = self.inner.downloads.lock().unwrap();
+        self.expire(&mut downloads, Instant::now());
+        let current = sorted(
+            downloads
+                .values()
+                .filter(|reported| reported.progress.state == ProgressState::InProgress as i32),
+        );
+        (current, self.inner.sender.subscribe())
+    }
+
+    /// Streams the downloads in flight, then every update to them.
+    ///
+    /// A subscriber that falls behind gets the latest report of every download that has not
+    /// expired instead of the updates it missed, finished ones included.
+    pub fn watch(&self) -> ReceiverStream<ui::DownloadProgress> {
+        let (current, mut updates) = self.subscribe();
+        let (tx, rx) = mpsc::channel(16);
+        let tracker = self.clone();
+        tokio::spawn(async move {
+            let mut batch = current;
+            loop {
+                for progress in batch {
+                    if tx.send(progress).await.is_err() {
+                        return;
+                    }
+                }
+                batch = tokio::select! {
+                    update = updates.recv() => match update {
+                        Ok(progress) => vec![progress],
+                        Err(broadcast::error::RecvError::Lagged(_)) => tracker.latest(),
+                        Err(broadcast::error::RecvError::Closed) => return,
+                    },
+                    _ = tx.closed() => return,
+                };
+            }
+        });
+        ReceiverStream::new(rx)
+    }
+
+    fn latest(&self) -> Vec<ui::DownloadProgress> {
+        let mut downloads = self.inner.downloads.lock().unwrap();
+        self.expire(&mut downloads, Instant::now());
+        sorted(downloads.values())
+    }
+}
+
+fn sorted<'a>(reported: impl Iterator<Item = &'a Reported>) -> Vec<ui::DownloadProgress> {
+    let mut progress: Vec<_> = reported.map(|reported| reported.progress.clone()).collect();
+    progress.sort_by(|a, b| (&a.origin, a.download_id).cmp(&(&b.origin, b.download_id)));
+    progress
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use tokio_stream::StreamExt;
+
+    use crate::grpc::ui::{DownloadProgress, ProgressState};
+    use crate::progress::{ProgressTracker, EXPIRY};
+
+    fn progress(download_id: i32, state: ProgressState) -> DownloadProgress {
+        DownloadProgress {
+            origin: "chrome-extension://abc/".to_string(),
+            download_id,
+            url: "https://example.com/file".to_string(),
+            filename: "/tmp/file".to_string(),
+            bytes_received: 10,
+            total_bytes: 20,
+            state: state as i32,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn finished_downloads_are_dropped() {
+        let tracker = ProgressTracker::default();
+        tracker.report(progress(1, ProgressState::InProgress));
+        tracker.report(progress(2, ProgressState::InProgress));
+        tracker.report(progress(1, ProgressState::Complete));
+
+        let (current, mut updates) = tracker.subscribe();
+        assert_eq!(current, vec![progress(2, ProgressState::InProgress)]);
+
+        tracker.report(progress(2, ProgressState::Interrupted));
+        assert_eq!(
+            updates.try_recv().unwrap(),
+            progress(2, ProgressState::Interrupted)
+        );
+        assert!(tracker.subscribe().0.is_empty());
+    }
+
+    #[test]
+    fn downloads_expire_without_reports() {
+        let tracker = ProgressTracker::default();
+        let start = Instant::now();
+        tracker.report_at(progress(1, ProgressState::InProgress), start);
+        let (_, mut updates) = tracker.subscribe();
+
+        let later = start + EXPIRY + Duration::from_secs(1);
+        tracker.report_at(progress(2, ProgressState::InProgress), later);
+
+        assert_eq!(
+            updates.try_recv().unwrap(),
+            progress(2, ProgressState::InProgress)
+        );
+        let expired = updates.try_recv().unwrap();
+        assert_eq!(expired.download_id, 1);
+        assert_eq!(expired.state, ProgressState::Interrupted as i32);
+        assert_eq!(
+            tracker.subscribe().0,
+            vec![progress(2, ProgressState::InProgress)]
+        );
+    }
+
+    #[tokio::test]
+    async fn lagging_watcher_gets_the_latest_reports() {
+        let tracker = ProgressTracker::default();
+        let mut watch = tracker.watch();
+        let mut last = progress(1, ProgressState::InProgress);
+        for bytes_received in 0..300 {
+            last.bytes_received = bytes_received;
+            tracker.report(last.clone());
+        }
+        tracker.report(progress(2, ProgressState::Complete));
+
+        assert_eq!(watch.next().await.unwrap(), last);
+        assert_eq!(
+            watch.next().await.unwrap(),
+            progress(2, ProgressState::Complete)
+        );
+    }
+}
@@ -0,0 +1,520 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use prisma_client_rust::{Direction, QueryError};
+use prisma_codegen::{download_job, PrismaClient};
+use tokio::sync::{oneshot, Mutex, Notify, OwnedSemaphorePermit, Semaphore};
+use tonic::Status;
+
+use crate::config::file::FileConfig;
+use crate::downloader::{self, Download};
+use crate::grpc::{
+    chrome_extension,
+    ui::{self, JobState},
+};
+use crate::storage::{self, FileStore, IncomingFile};
+use crate::{error_io_to_tonic, error_prisma_to_tonic};
+
+/// How many downloads may run at the same time
+#[derive(Clone, Copy)]
+pub struct Limits {
+    pub global: usize,
+    pub per_host: usize,
+}
+
+/// Why a running job is stopped
+enum Stop {
+    Pause,
+    Cancel,
+}
+
+/// Downloads that edman-main fetches itself, persisted in the `DownloadJob` table.
+///
+/// Queued jobs are started in order of priority, then of creation, as long as
+/// the global and the per-host limits allow it.
+#[derive(Clone)]
+pub struct Queue {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    prisma_client: Arc<PrismaClient>,
+    file_config: Arc<FileConfig>,
+    store: FileStore,
+    http_client: reqwest::Client,
+    limits: Limits,
+    global: Arc<Semaphore>,
+    hosts: std::sync::Mutex<HashMap<String, Arc<Semaphore>>>,
+    /// Jobs that are still fetching and can be stopped.
+    /// Held while the state of a job changes, so that the RPCs and the workers do not race.
+    running: Mutex<HashMap<i32, oneshot::Sender<Stop>>>,
+    /// `Cookie` headers of the jobs, which are credentials and never written to the database.
+    /// Jobs requeued after a restart run without them.
+    cookies: std::sync::Mutex<HashMap<i32, String>>,
+    wakeup: Notify,
+}
+
+impl Queue {
+    pub fn new(
+        prisma_client: Arc<PrismaClient>,
+        file_config: Arc<FileConfig>,
+        store: FileStore,
+        limits: Limits,
+    ) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                prisma_client,
+                file_config,
+                store,
+                http_client: reqwest::Client::new(),
+                limits,
+                global: Arc::new(Semaphore::new(limits.global.max(1))),
+                hosts: Default::default(),
+                running: Default::default(),
+                cookies: Default::default(),
+                wakeup: Notify::new(),
+            }),
+        }
+    }
+
+    /// Requeues the jobs interrupted by the last shutdown and starts dispatching.
+    pub async fn start(&self) -> Result<(), QueryError> {
+        self.inner
+            .prisma_client
+            .download_job()
+            .update_many(
+                vec![download_job::state::equals(JobState::Running as i32)],
+                vec![download_job::SetParam::SetState(JobState::Queued as i32)],
+            )
+            .exec()
+            .await?;
+
+        tokio::spawn(self.clone().dispatch());
+        Ok(())
+    }
+
+    pub async fn enqueue(
+        &self,
+        profile: &str,
+        request: &chrome_extension::EnqueueDownloadRequest,
+    ) -> Result<i32, Status> {
+        let url = reqwest::Url::parse(&request.url)
+            .map_err(|err| Status::invalid_argument(format!("Invalid url: {}", err)))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(Status::invalid_argument("url must be http or https."));
+        }
+        storage::split_save_path(&request.save_path)?;
+        let page_origin = crate::quota::normalize_origin(request.page_origin.as_deref())?;
+
+        let job = self
+            .inner
+            .prisma_client
+            .download_job()
+            .create(
+                url.to_string(),
+                request.save_path.join("\n"),
+                request.key.to_owned(),
+                profile.to_owned(),
+                url.host_str().unwrap_or_default().to_owned(),
+                vec![
+                    download_job::SetParam::SetOrigin(
+                        request.origin.to_owned().unwrap_or_default(),
+                    ),
+                    download_job::SetParam::SetPageOrigin(page_origin.unwrap_or_default()),
+                    download_job::SetParam::SetSha256(
+                        request.sha256.to_owned().unwrap_or_default(),
+                    ),
+                    download_job::SetParam::SetPriority(request.priority),
+                ],
+            )
+            .exec()
+            .await
+            .map_err(error_prisma_to_tonic)?;
+        let cookie = request.cookie.as_deref().unwrap_or_default();
+        if !cookie.is_empty() {
+            let mut cookies = self.inner.cookies.lock().unwrap();
+            cookies.insert(job.id, cookie.to_owned());
+        }
+
+        self.inner.wakeup.notify_one();
+        Ok(job.id)
+    }
+
+    pub async fn list(&self) -> Result<Vec<ui::DownloadJob>, Status> {
+        let jobs = self
+            .inner
+            .prisma_client
+            .download_job()
+            .find_many(vec![])
+            .order_by(download_job::id::order(Direction::Asc))
+            .exec()
+            .await
+            .map_err(error_prisma_to_tonic)?;
+
+        Ok(jobs.into_iter().map(to_message).collect())
+    }
+
+    pub async fn pause(&self, id: i32) -> Result<ui::DownloadJob, Status> {
+        self.stop(id, Stop::Pause).await
+    }
+
+    pub async fn cancel(&self, id: i32) -> Result<ui::DownloadJob, Status> {
+        self.stop(id, Stop::Cancel).await
+    }
+
+    pub async fn resume(&self, id: i32) -> Result<ui::DownloadJob, Status> {
+        self.requeue(id, &[JobState::Paused]).await
+    }
+
+    pub async fn retry(&self, id: i32) -> Result<ui::DownloadJob, Status> {
+        self.requeue(id, &[JobState::Failed, JobState::Cancelled])
+            .await
+    }
+
+    async fn stop(&self, id: i32, stop: Stop) -> Result<ui::DownloadJob, Status> {
+        let mut running = self.inner.running.lock().await;
+        let job = self.find(id).await?;
+
+        let (stoppable, new_state) = match stop {
+            Stop::Pause => (&[JobState::Queued, JobState::Running][..], JobState::Paused),
+            Stop::Cancel => (
+                &[
+                    JobState::Queued,
+                    JobState::Running,
+                    JobState::Paused,
+                    JobState::Failed,
+                ][..],
+                JobState::Cancelled,
+            ),
+        };
+        check_state(&job, stoppable)?;
+
+        if job.state == JobState::Running as i32 {
+            let Some(stop_tx) = running.remove(&id) else {
+                return Err(Status::failed_precondition(
+                    "The download has finished and is being stored",
+                ));
+            };
+            // The worker removes the partial file of a cancelled job itself
+            let _ = stop_tx.send(stop);
+        } else if matches!(stop, Stop::Cancel) {
+            self.remove_partial(&job).await;
+        }
+        if matches!(stop, Stop::Cancel) {
+            self.inner.cookies.lock().unwrap().remove(&id);
+        }
+
+        self.set_state(id, new_state, None).await
+    }
+
+    async fn requeue(&self, id: i32, from: &[JobState]) -> Result<ui::DownloadJob, Status> {
+        let _running = self.inner.running.lock().await;
+        check_state(&self.find(id).await?, from)?;
+
+        let job = self.set_state(id, JobState::Queued, Some("")).await?;
+        self.inner.wakeup.notify_one();
+        Ok(job)
+    }
+
+    async fn dispatch(self) {
+        loop {
+            let global = self
+                .inner
+                .global
+                .clone()
+                .acquire_owned()
+                .await
+                .expect("The semaphore is never closed");
+
+            match self.start_next(global).await {
+                Ok(true) => {}
+                Ok(false) => self.inner.wakeup.notified().await,
+                Err(err) => {
+                    eprintln!("Cannot start a download: {:?}", err);
+                    self.inner.wakeup.notified().await;
+                }
+            }
+        }
+    }
+
+    /// Starts the first queued job whose host is below its limit.
+    async fn start_next(&self, global: OwnedSemaphorePermit) -> Result<bool, QueryError> {
+        let mut running = self.inner.running.lock().await;
+        let queued = self
+            .inner
+            .prisma_client
+            .download_job()
+            .find_many(vec![download_job::state::equals(JobState::Queued as i32)])
+            .order_by(download_job::priority::order(Direction::Desc))
+            .order_by(download_job::id::order(Direction::Asc))
+            .exec()
+            .await?;
+
+        for job in queued {
+            let Ok(host) = self.host_semaphore(&job.host).try_acquire_owned() else {
+                continue;
+            };
+
+            let job = self
+                .inner
+                .prisma_client
+                .download_job()
+                .update(
+                    download_job::UniqueWhereParam::IdEquals(job.id),
+                    vec![
+                        download_job::SetParam::SetState(JobState::Running as i32),
+                        download_job::SetParam::SetAttempts(job.attempts + 1),
+                    ],
+                )
+                .exec()
+                .await?;
+
+            let (stop_tx, stop_rx) = oneshot::channel();
+            running.insert(job.id, stop_tx);
+            tokio::spawn(self.clone().run(job, stop_rx, [global, host]));
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+
+    async fn run(
+        self,
+        job: download_job::Data,
+        stop_rx: oneshot::Receiver<Stop>,
+        permits: [OwnedSemaphorePermit; 2],
+    ) {
+        let result = self.execute(&job, stop_rx).await;
+
+        // A stopped job already got its new state from `stop`
+        if let Some(result) = result {
+            let (state, error) = match result {
+                Ok(()) => {
+                    self.inner.cookies.lock().unwrap().remove(&job.id);
+                    (JobState::Completed, String::new())
+                }
+                Err(status) => (JobState::Failed, status.message().to_owned()),
+            };
+            if let Err(status) = self.set_state(job.id, state, Some(&error)).await {
+                eprintln!("Cannot update download {}: {}", job.id, status.message());
+            }
+        }
+
+        drop(permits);
+        self.inner.wakeup.notify_one();
+    }
+
+    /// Returns `None` if the job was stopped.
+    async fn execute(
+        &self,
+        job: &download_job::Data,
+        mut stop_rx: oneshot::Receiver<Stop>,
+    ) -> Option<Result<(), Status>> {
+        let prepared = self.prepare(job).await;
+        let (config, download, partial) = match prepared {
+            Ok(prepared) => prepared,
+            Err(status) => return self.finish_fetch(job.id).await.then_some(Err(status)),
+        };
+
+        let fetched = tokio::select! {
+            fetched = downloader::fetch(&self.inner.http_client, &download, &partial) => fetched,
+            stop = &mut stop_rx => {
+                if let Ok(Stop::Cancel) = stop {
+                    let _ = downloader::discard(&partial).await;
+                }
+                return None;
+            }
+        };
+
+        // From here on the job can no longer be stopped
+        if !self.finish_fetch(job.id).await {
+            if let Ok(Stop::Cancel) = stop_rx.try_recv() {
+                let _ = downloader::discard(&partial).await;
+            }
+            return None;
+        }
+        if let Err(err) = fetched {
+            return Some(Err(Status::unavailable(err.to_string())));
+        }
+
+        let save_path = split_lines(&job.save_path);
+        let origin = Some(&job.origin[..]).filter(|origin| !origin.is_empty());
+        let file = IncomingFile {
+            source: &partial,
+            save_path: &save_path,
+            key: &job.key,
+            page_origin: Some(&job.page_origin[..]).filter(|origin| !origin.is_empty()),
+        };
+        let result = self
+            .inner
+            .store
+            .store_file(&config, &job.profile, origin, file)
+            .await;
+        if result.is_err() {
+            let _ = downloader::discard(&partial).await;
+        }
+        Some(result.map(|_| ()))
+    }
+
+    async fn prepare(
+        &self,
+        job: &download_job::Data,
+    ) -> Result<(Box<crate::config::Config>, Download, PathBuf), Status> {
+        let config = crate::config::resolve(
+            &self.inner.prisma_client,
+            &self.inner.file_config,
+            &job.profile,
+        )
+        .await?;
+        let url = reqwest::Url::parse(&job.url)
+            .map_err(|err| Status::invalid_argument(format!("Invalid url: {}", err)))?;
+        let partial = storage::partial_path(&config, &split_lines(&job.save_path), &job.url)?;
+        utils::create_parent_dirs(&partial).map_err(error_io_to_tonic)?;
+
+        let download = Download {
+            url,
+            cookie: self.inner.cookies.lock().unwrap().get(&job.id).cloned(),
+            sha256: Some(job.sha256.to_owned()).filter(|sha256| !sha256.is_empty()),
+        };
+        Ok((config, download, partial))
+    }
+
+    /// Returns `false` if the job has been stopped in the meantime.
+    async fn finish_fetch(&self, id: i32) -> bool {
+        self.inner.running.lock().await.remove(&id).is_some()
+    }
+
+    async fn remove_partial(&self, job: &download_job::Data) {
+        let config = crate::config::resolve(
+            &self.inner.prisma_client,
+            &self.inner.file_config,
+            &job.profile,
+        )
+        .await;
+        let partial = config.ok().and_then(|config| {
+            storage::partial_path(&config, &split_lines(&job.save_path), &job.url).ok()
+        });
+        if let Some(partial) = partial {
+            let _ = downloader::discard(&partial).await;
+        }
+    }
+
+    fn host_semaphore(&self, host: &str) -> Arc<Semaphore> {
+        let mut hosts = self.inner.hosts.lock().unwrap();
+        hosts
+            .entry(host.to_owned())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.inner.limits.per_host.max(1))))
+            .clone()
+    }
+
+    async fn find(&self, id: i32) -> Result<download_job::Data, Status> {
+        self.inner
+            .prisma_client
+            .download_job()
+            .find_unique(download_job::UniqueWhereParam::IdEquals(id))
+            .exec()
+            .await
+            .map_err(error_prisma_to_tonic)?
+            .ok_or_else(|| Status::not_found(format!("Download {} not found", id)))
+    }
+
+    async fn set_state(
+        &self,
+        id: i32,
+        state: JobState,
+        last_error: Option<&str>,
+    ) -> Result<ui::DownloadJob, Status> {
+        let mut params = vec![download_job::SetParam::SetState(state as i32)];
+        if let Some(last_error) = last_error {
+            params.push(download_job::SetParam::SetLastError(last_error.to_owned()));
+        }
+
+        let job = self
+            .inner
+            .prisma_client
+            .download_job()
+            .update(download_job::UniqueWhereParam::IdEquals(id), params)
+            .exec()
+            .await
+            .map_err(error_prisma_to_tonic)?;
+        Ok(to_message(job))
+    }
+}
+
+fn check_state(job: &download_job::Data, allowed: &[JobState]) -> Result<(), Status> {
+    if allowed.iter().any(|state| *state as i32 == job.state) {
+        return Ok(());
+    }
+
+    let state = JobState::try_from(job.state)
+        .map(|state| state.as_str_name())
+        .unwrap_or("UNKNOWN");
+    Err(Status::failed_precondition(format!(
+        "Download {} is {}",
+        job.id, state
+    )))
+}
+
+fn split_lines(s: &str) -> Vec<String> {
+    s.split('\n')
+        .filter(|s| !s.is_empty())
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+fn to_message(job: download_job::Data) -> ui::DownloadJob {
+    ui::DownloadJob {
+        id: job.id,
+        created_at: job.created_at.timestamp(),
+        save_path: split_lines(&job.save_path),
+        url: job.url,
+        key: job.key,
+        profile: job.profile,
+        state: job.state,
+        priority: job.priority,
+        attempts: job.attempts,
+        last_error: job.last_error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::config::file::FileConfig;
+    use crate::events::EventBus;
+    use crate::grpc::chrome_extension::EnqueueDownloadRequest;
+    use crate::queue::{Limits, Queue};
+    use crate::storage::FileStore;
+    use crate::temp_db::TempDatabase;
+
+    #[tokio::test]
+    async fn cookies_are_not_stored() {
+        let database = TempDatabase::create().await.unwrap();
+        let client = database.client.to_owned();
+        let store = FileStore::new(client.to_owned(), EventBus::default());
+        let limits = Limits {
+            global: 1,
+            per_host: 1,
+        };
+        // Not started, so that the job stays queued
+        let queue = Queue::new(client, Arc::new(FileConfig::default()), store, limits);
+
+        let request = EnqueueDownloadRequest {
+            url: "https://example.com/a.txt".to_string(),
+            save_path: vec!["a.txt".to_string()],
+            key: "a".to_string(),
+            cookie: Some("session=0123456789abcdef".to_string()),
+            ..Default::default()
+        };
+        let id = queue.enqueue("default", &request).await.unwrap();
+
+        let db = std::fs::read(database.path()).unwrap();
+        assert!(!db.windows(16).any(|window| window == b"0123456789abcdef"));
+        assert!(queue.inner.cookies.lock().unwrap().contains_key(&id));
+
+        queue.cancel(id).await.unwrap();
+        assert!(queue.inner.cookies.lock().unwrap().is_empty());
+    }
+}
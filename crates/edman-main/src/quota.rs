@@ -0,0 +1,227 @@
+use prisma_client_rust::{raw, PrismaValue};
+use prisma_codegen::PrismaClient;
+use serde::Deserialize;
+use tonic::Status;
+
+use crate::config::Config;
+use crate::error_prisma_to_tonic;
+use crate::events::EventBus;
+use crate::grpc::ui;
+
+/// Checks whether a file of `incoming` bytes from a page of `page_origin` still fits into the
+/// quotas of `config`.
+///
+/// Returns a [`ui::QuotaWarning`] for each quota whose warning threshold the file makes the usage
+/// cross. Pass them to [`emit`] once the file is registered, so that nothing is reported for a
+/// registration that is rolled back.
+pub async fn check(
+    client: &PrismaClient,
+    config: &Config,
+    profile: &str,
+    page_origin: Option<&str>,
+    incoming: u64,
+) -> Result<Vec<ui::QuotaWarning>, Status> {
+    let usage = Usage {
+        profile,
+        warning_percent: config.quota_warning_percent,
+        incoming,
+    };
+    let mut warnings = vec![];
+
+    if config.save_quota_bytes > 0 {
+        let used = used_bytes(client, Scope::Profile(profile)).await?;
+        warnings.extend(usage.check(None, used, config.save_quota_bytes)?);
+    }
+
+    let origin_quota = page_origin.and_then(|page_origin| {
+        config.origin_quotas.iter().find(|quota| {
+            quota.bytes > 0
+                && normalize_origin(Some(&quota.origin))
+                    .ok()
+                    .flatten()
+                    .as_deref()
+                    == Some(page_origin)
+        })
+    });
+    if let (Some(quota), Some(page_origin)) = (origin_quota, page_origin) {
+        let used = used_bytes(client, Scope::Origin(page_origin)).await?;
+        warnings.extend(usage.check(Some(page_origin), used, quota.bytes)?);
+    }
+
+    Ok(warnings)
+}
+
+/// Reports the warnings that [`check`] returned.
+pub fn emit(events: &EventBus, warnings: Vec<ui::QuotaWarning>) {
+    for warning in warnings {
+        eprintln!(
+            "Quota of {} is almost exhausted: {} of {} bytes used",
+            target(&warning.profile, warning.origin.as_deref()),
+            warning.used_bytes,
+            warning.limit_bytes
+        );
+        events.emit(ui::event::Kind::QuotaWarning(warning));
+    }
+}
+
+/// Reduces the URL of a page to its origin, e.g. `https://example.com`, which origin quotas
+/// are keyed by.
+///
+/// Returns `None` if no page is given.
+pub fn normalize_origin(page: Option<&str>) -> Result<Option<String>, Status> {
+    let Some(page) = page.filter(|page| !page.is_empty()) else {
+        return Ok(None);
+    };
+    let origin = reqwest::Url::parse(page)
+        .map_err(|err| Status::invalid_argument(format!("pageOrigin is not a URL: {}", err)))?
+        .origin();
+    if !origin.is_tuple() {
+        return Err(Status::invalid_argument(
+            "pageOrigin must have a scheme and a host.",
+        ));
+    }
+    Ok(Some(origin.ascii_serialization()))
+}
+
+fn target(profile: &str, origin: Option<&str>) -> String {
+    match origin {
+        Some(origin) => format!("origin \"{}\"", origin),
+        None => format!("profile \"{}\"", profile),
+    }
+}
+
+/// Files whose sizes count towards a quota
+enum Scope<'a> {
+    Profile(&'a str),
+    Origin(&'a str),
+}
+
+#[derive(Deserialize)]
+struct Used {
+    used: i64,
+}
+
+async fn used_bytes(client: &PrismaClient, scope: Scope<'_>) -> Result<u64, Status> {
+    let query = match scope {
+        Scope::Profile(profile) => raw!(
+            r#"SELECT COALESCE(SUM("size"), 0) AS "used" FROM "File" WHERE "profile" = {}"#,
+            PrismaValue::String(profile.to_owned())
+        ),
+        Scope::Origin(origin) => raw!(
+            r#"SELECT COALESCE(SUM("size"), 0) AS "used" FROM "File" WHERE "page_origin" = {}"#,
+            PrismaValue::String(origin.to_owned())
+        ),
+    };
+    let rows: Vec<Used> = client
+        ._query_raw(query)
+        .exec()
+        .await
+        .map_err(error_prisma_to_tonic)?;
+
+    Ok(rows.first().map_or(0, |row| row.used.max(0) as u64))
+}
+
+struct Usage<'a> {
+    profile: &'a str,
+    warning_percent: u32,
+    incoming: u64,
+}
+
+impl Usage<'_> {
+    fn check(
+        &self,
+        origin: Option<&str>,
+        used: u64,
+        limit: u64,
+    ) -> Result<Option<ui::QuotaWarning>, Status> {
+        let total = used.saturating_add(self.incoming);
+
+        if total > limit {
+            return Err(Status::resource_exhausted(format!(
+                "Quota of {} exceeded: {} of {} bytes used, {} bytes incoming",
+                target(self.profile, origin),
+                used,
+                limit,
+                self.incoming
+            )));
+        }
+
+        let threshold = (limit as u128 * self.warning_percent as u128 / 100) as u64;
+        if self.warning_percent > 0 && used < threshold && total >= threshold {
+            return Ok(Some(ui::QuotaWarning {
+                profile: self.profile.to_owned(),
+                origin: origin.map(ToOwned::to_owned),
+                used_bytes: total,
+                limit_bytes: limit,
+            }));
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::quota::{normalize_origin, used_bytes, Scope};
+    use crate::temp_db::TempDatabase;
+
+    #[tokio::test]
+    async fn sizes_are_summed_per_scope() {
+        let database = TempDatabase::create().await.unwrap();
+        let client = &database.client;
+        assert_eq!(
+            used_bytes(client, Scope::Profile("default")).await.unwrap(),
+            0
+        );
+
+        for (key, size) in [("a", 3), ("b", 4)] {
+            client
+                .file()
+                .create(
+                    key.to_owned(),
+                    format!("{}.txt", key),
+                    vec![
+                        prisma_codegen::file::SetParam::SetPageOrigin(
+                            "https://example.com".to_owned(),
+                        ),
+                        prisma_codegen::file::SetParam::SetSize(size),
+                    ],
+                )
+                .exec()
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            used_bytes(client, Scope::Profile("default")).await.unwrap(),
+            7
+        );
+        assert_eq!(
+            used_bytes(client, Scope::Origin("https://example.com"))
+                .await
+                .unwrap(),
+            7
+        );
+        assert_eq!(
+            used_bytes(client, Scope::Origin("https://example.org"))
+                .await
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn pages_are_reduced_to_their_origin() {
+        assert_eq!(
+            normalize_origin(Some("https://example.com:443/a/b?c")).unwrap(),
+            Some("https://example.com".to_string())
+        );
+        assert_eq!(
+            normalize_origin(Some("http://example.com:8080")).unwrap(),
+            Some("http://example.com:8080".to_string())
+        );
+        assert_eq!(normalize_origin(None).unwrap(), None);
+        assert!(normalize_origin(Some("example.com")).is_err());
+        assert!(normalize_origin(Some("data:text/plain,a")).is_err());
+    }
+}
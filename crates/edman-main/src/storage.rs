@@ -0,0 +1,584 @@
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use prisma_codegen::PrismaClient;
+use sha2::{Digest, Sha256};
+use tonic::{metadata::MetadataValue, Status};
+
+use crate::config::Config;
+use crate::events::EventBus;
+use crate::grpc::{chrome_extension, ui};
+use crate::{error_io_to_tonic, error_prisma_to_tonic};
+
+/// Where a finished download should be moved to
+pub struct Placement {
+    /// Absolute destination, or `None` if the download should be skipped
+    pub target: Option<PathBuf>,
+    /// Destination relative to the save directory, with `/` as separator
+    pub relative_path: String,
+}
+
+/// Resolves `download_path` inside the download subdirectory of `config`.
+pub fn download_source(config: &Config, download_path: &str) -> Result<PathBuf, Status> {
+    let relative = Path::new(download_path);
+    let is_plain = relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    if !is_plain || !relative.starts_with(&config.download_subdirectory) {
+        return Err(path_invalid(format!(
+            "downloadPath must be a relative path inside \"{}\".",
+            config.download_subdirectory
+        )));
+    }
+
+    Ok(PathBuf::from(&config.download_directory).join(relative))
+}
+
+/// Decides where `source` ends up for the path components `save_path`.
+pub fn place(config: &Config, source: &Path, save_path: &[String]) -> Result<Placement, Status> {
+    let (file_name, directories) = split_save_path(save_path)?;
+
+    let save_dir = PathBuf::from(&config.save_file_directory).join(directories.join("/"));
+    let target =
+        crate::collision::resolve(config.collision_policy(), source, save_dir.join(file_name))
+            .map_err(error_io_to_tonic)?;
+
+    let final_name = match target {
+        Some(ref target) => target
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| path_invalid("savePath must be valid UTF-8."))?,
+        None => file_name,
+    };
+    let relative_path = directories
+        .iter()
+        .map(|p| &p[..])
+        .chain([final_name])
+        .collect::<Vec<_>>()
+        .join("/");
+
+    Ok(Placement {
+        target,
+        relative_path,
+    })
+}
+
+/// File that a download from `url` to `save_path` is streamed into until it is complete.
+///
+/// It lives next to the final location, so that moving it there is a rename.
+pub fn partial_path(config: &Config, save_path: &[String], url: &str) -> Result<PathBuf, Status> {
+    let (file_name, directories) = split_save_path(save_path)?;
+
+    let url_hash: String = Sha256::digest(url.as_bytes())[..4]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    Ok(PathBuf::from(&config.save_file_directory)
+        .join(directories.join("/"))
+        .join(format!(".{}.{}.edman-part", file_name, url_hash)))
+}
+
+/// Splits `save_path` into the file name and its directories, rejecting anything that could escape the save directory.
+pub fn split_save_path(save_path: &[String]) -> Result<(&String, &[String]), Status> {
+    let Some((file_name, directories)) = save_path.split_last() else {
+        return Err(path_invalid("savePath must not be empty."));
+    };
+    if save_path
+        .iter()
+        .any(|p| p.is_empty() || p.contains(['/', '\\']) || p.contains(".."))
+    {
+        return Err(path_invalid("savePath must not contain slashes or dots."));
+    }
+
+    Ok((file_name, directories))
+}
+
+/// `InvalidArgument` that the native host reports as an invalid path
+fn path_invalid(message: impl Into<String>) -> Status {
+    let mut status = Status::invalid_argument(message);
+    status
+        .metadata_mut()
+        .insert(utils::PATH_INVALID_KEY, MetadataValue::from_static("true"));
+    status
+}
+
+/// Moves `source` to `target`, creating the parent directories as needed.
+pub fn move_file(source: &Path, target: &Path) -> std::io::Result<()> {
+    utils::create_parent_dirs(target)?;
+    crate::fs_move::move_file(source, target)
+}
+
+/// File moved into the save directory, which can still be moved back
+struct MovedFile {
+    source: PathBuf,
+    target: PathBuf,
+    /// Where the file that was at `target` before has been set aside
+    replaced: Option<PathBuf>,
+}
+
+impl MovedFile {
+    /// Moves `source` to `target`, setting aside the file it replaces.
+    fn move_in(source: &Path, target: &Path) -> std::io::Result<Self> {
+        let replaced = if target.exists() {
+            let file_name = target.file_name().unwrap_or_default().to_string_lossy();
+            let aside = target.with_file_name(format!(".{}.edman-replaced", file_name));
+            std::fs::rename(target, &aside)?;
+            Some(aside)
+        } else {
+            None
+        };
+
+        if let Err(err) = move_file(source, target) {
+            if let Some(ref aside) = replaced {
+                std::fs::rename(aside, target)?;
+            }
+            return Err(err);
+        }
+
+        Ok(Self {
+            source: source.to_owned(),
+            target: target.to_owned(),
+            replaced,
+        })
+    }
+
+    /// Moves the file back to where it came from and restores the file it replaced.
+    fn undo(self) -> std::io::Result<()> {
+        move_file(&self.target, &self.source)?;
+        if let Some(aside) = self.replaced {
+            std::fs::rename(aside, &self.target)?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the file it replaced.
+    fn keep(self) {
+        if let Some(aside) = self.replaced {
+            if let Err(err) = std::fs::remove_file(&aside) {
+                eprintln!("Cannot delete {}: {}", aside.display(), err);
+            }
+        }
+    }
+}
+
+/// Change to the file system that is only final once the record of the file is committed
+enum Pending {
+    /// Skipped download, which is deleted on commit
+    Delete(PathBuf),
+    Moved(MovedFile),
+}
+
+impl Pending {
+    fn commit(self) -> std::io::Result<()> {
+        match self {
+            Pending::Delete(source) => std::fs::remove_file(source),
+            Pending::Moved(moved) => {
+                moved.keep();
+                Ok(())
+            }
+        }
+    }
+
+    fn roll_back(self) -> std::io::Result<()> {
+        match self {
+            Pending::Delete(_) => Ok(()),
+            Pending::Moved(moved) => moved.undo(),
+        }
+    }
+
+    fn roll_back_or_log(self) {
+        if let Err(err) = self.roll_back() {
+            eprintln!("Cannot move a file back to the download directory: {}", err);
+        }
+    }
+}
+
+/// Download that has been moved into the save directory, or is to be skipped, but is not
+/// registered yet
+struct Staged {
+    /// Destination relative to the save directory, with `/` as separator
+    relative_path: String,
+    size: u64,
+    pending: Pending,
+}
+
+impl Staged {
+    fn is_skipped(&self) -> bool {
+        matches!(self.pending, Pending::Delete(_))
+    }
+}
+
+/// Moves `source` to where it belongs for `save_path`, in a way that can still be undone.
+///
+/// A skipped download is not deleted yet, nor is the file that it replaced.
+fn stage(config: &Config, source: &Path, save_path: &[String]) -> Result<Staged, Status> {
+    let size = source
+        .metadata()
+        .map_err(|err| Status::not_found(format!("Cannot read {}: {}", source.display(), err)))?
+        .len();
+    let placement = place(config, source, save_path)?;
+
+    let pending = match placement.target {
+        Some(ref target) => {
+            Pending::Moved(MovedFile::move_in(source, target).map_err(error_io_to_tonic)?)
+        }
+        None => Pending::Delete(source.to_owned()),
+    };
+    Ok(Staged {
+        relative_path: placement.relative_path,
+        size,
+        pending,
+    })
+}
+
+/// Runs file system work off the async runtime, since moving a file to another file system
+/// copies it and resolving a collision may hash it.
+async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> Result<T, Status> + Send + 'static,
+) -> Result<T, Status> {
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(|err| Status::internal(format!("File system task failed: {}", err)))?
+}
+
+/// Rolls back `undone`, in reverse in case several files replaced the same one, and commits
+/// `kept`, logging what fails.
+async fn settle(undone: Vec<Pending>, kept: Vec<Pending>) {
+    let settled = blocking(move || {
+        for pending in undone.into_iter().rev() {
+            pending.roll_back_or_log();
+        }
+        for pending in kept {
+            if let Err(err) = pending.commit() {
+                eprintln!("Cannot delete a skipped download: {}", err);
+            }
+        }
+        Ok(())
+    })
+    .await;
+    if let Err(status) = settled {
+        eprintln!("{}", status.message());
+    }
+}
+
+pub struct FileRecord<'a> {
+    pub key: &'a str,
+    pub path: &'a str,
+    pub size: u64,
+    /// Page the file was downloaded from, which origin quotas apply to
+    pub page_origin: Option<&'a str>,
+}
+
+/// Finished download that is to be moved into the save directory
+pub struct IncomingFile<'a> {
+    pub source: &'a Path,
+    /// Path components relative to the save directory
+    pub save_path: &'a [String],
+    pub key: &'a str,
+    /// Page the file was downloaded from, which origin quotas apply to
+    pub page_origin: Option<&'a str>,
+}
+
+/// Registers files in the save directory in the database
+#[derive(Clone)]
+pub struct FileStore {
+    pub prisma_client: Arc<PrismaClient>,
+    pub events: EventBus,
+}
+
+impl FileStore {
+    pub fn new(prisma_client: Arc<PrismaClient>, events: EventBus) -> Self {
+        Self {
+            prisma_client,
+            events,
+        }
+    }
+
+    /// Moves a finished download into the save directory and registers it.
+    pub async fn store_file(
+        &self,
+        config: &Config,
+        profile: &str,
+        origin: Option<&str>,
+        file: IncomingFile<'_>,
+    ) -> Result<chrome_extension::CommitDownloadReply, Status> {
+        let staged = {
+            let config = config.clone();
+            let source = file.source.to_owned();
+            let save_path = file.save_path.to_owned();
+            blocking(move || stage(&config, &source, &save_path)).await?
+        };
+
+        let id = match staged.is_skipped() {
+            true => None,
+            false => {
+                let record = FileRecord {
+                    key: file.key,
+                    path: &staged.relative_path,
+                    size: staged.size,
+                    page_origin: file.page_origin,
+                };
+                match self.create_record(config, profile, origin, record).await {
+                    Ok(id) => Some(id),
+                    Err(status) => {
+                        // Leave the file where it was so that it can be stored again
+                        let pending = staged.pending;
+                        blocking(move || pending.roll_back().map_err(error_io_to_tonic)).await?;
+                        return Err(status);
+                    }
+                }
+            }
+        };
+        let pending = staged.pending;
+        blocking(move || pending.commit().map_err(error_io_to_tonic)).await?;
+
+        Ok(chrome_extension::CommitDownloadReply {
+            id,
+            path: staged.relative_path,
+        })
+    }
+
+    /// Stores finished downloads like [`FileStore::store_file`], registering them in one transaction.
+    ///
+    /// Every file gets a result of its own, so one that fails does not keep the others from
+    /// being registered, and the quotas account for the files before it. The files are moved
+    /// before the transaction starts, so that it only holds the database for the inserts.
+    /// Skipped downloads and replaced files are only deleted once it is committed. If it fails
+    /// as a whole, the files are moved back to the download directory.
+    pub async fn store_files(
+        &self,
+        config: &Config,
+        profile: &str,
+        origin: Option<&str>,
+        files: &[chrome_extension::RegisterFilesItem],
+    ) -> Result<Vec<Result<chrome_extension::CommitDownloadReply, Status>>, Status> {
+        let staged: Vec<Result<Staged, Status>> = {
+            let config = config.clone();
+            let files = files.to_owned();
+            blocking(move || {
+                Ok(files
+                    .iter()
+                    .map(|file| {
+                        let source = download_source(&config, &file.download_path)?;
+                        stage(&config, &source, &file.save_path)
+                    })
+                    .collect())
+            })
+            .await?
+        };
+
+        let staged_in_transaction = &staged;
+        let transaction = self
+            .prisma_client
+            ._transaction()
+            .run(|client| async move {
+                // `None` for the files that are skipped or could not be moved
+                let mut inserted = vec![];
+                for (file, staged) in files.iter().zip(staged_in_transaction) {
+                    let result = match staged {
+                        Ok(staged) if !staged.is_skipped() => {
+                            let record = FileRecord {
+                                key: &file.key,
+                                path: &staged.relative_path,
+                                size: staged.size,
+                                page_origin: file.page_origin.as_deref(),
+                            };
+                            Some(check_and_insert(&client, config, profile, origin, record).await)
+                        }
+                        _ => None,
+                    };
+                    inserted.push(result);
+                }
+                Ok::<_, prisma_client_rust::QueryError>(inserted)
+            })
+            .await;
+
+        let inserted = match transaction {
+            Ok(inserted) => inserted,
+            Err(err) => {
+                let undone = staged
+                    .into_iter()
+                    .flatten()
+                    .map(|staged| staged.pending)
+                    .collect();
+                settle(undone, vec![]).await;
+                return Err(error_prisma_to_tonic(err));
+            }
+        };
+
+        let mut results = vec![];
+        let mut kept = vec![];
+        let mut undone = vec![];
+        for (staged, inserted) in staged.into_iter().zip(inserted) {
+            let staged = match staged {
+                Ok(staged) => staged,
+                Err(status) => {
+                    results.push(Err(status));
+                    continue;
+                }
+            };
+            let id = match inserted {
+                Some(Ok((id, warnings))) => {
+                    crate::quota::emit(&self.events, warnings);
+                    Some(id)
+                }
+                Some(Err(status)) => {
+                    undone.push(staged.pending);
+                    results.push(Err(status));
+                    continue;
+                }
+                None => None,
+            };
+            kept.push(staged.pending);
+            results.push(Ok(chrome_extension::CommitDownloadReply {
+                id,
+                path: staged.relative_path,
+            }));
+        }
+        settle(undone, kept).await;
+
+        Ok(results)
+    }
+
+    /// Checks the quotas and registers a file that is already in the save directory.
+    ///
+    /// Both happen in one transaction, so that concurrent registrations cannot exceed a quota together.
+    pub async fn create_record(
+        &self,
+        config: &Config,
+        profile: &str,
+        origin: Option<&str>,
+        record: FileRecord<'_>,
+    ) -> Result<i32, Status> {
+        let (id, warnings) = self
+            .prisma_client
+            ._transaction()
+            .run(|client| async move {
+                Ok::<_, prisma_client_rust::QueryError>(
+                    check_and_insert(&client, config, profile, origin, record).await,
+                )
+            })
+            .await
+            .map_err(error_prisma_to_tonic)??;
+        crate::quota::emit(&self.events, warnings);
+
+        Ok(id)
+    }
+}
+
+/// Inserts the record if it fits into the quotas, returning its id and the quota warnings
+/// that are due once the insert is committed.
+async fn check_and_insert(
+    client: &PrismaClient,
+    config: &Config,
+    profile: &str,
+    origin: Option<&str>,
+    record: FileRecord<'_>,
+) -> Result<(i32, Vec<ui::QuotaWarning>), Status> {
+    let page_origin = crate::quota::normalize_origin(record.page_origin)?;
+    let warnings =
+        crate::quota::check(client, config, profile, page_origin.as_deref(), record.size).await?;
+
+    let created = client
+        .file()
+        .create(
+            record.key.to_owned(),
+            record.path.to_owned(),
+            vec![
+                prisma_codegen::file::SetParam::SetProfile(profile.to_owned()),
+                prisma_codegen::file::SetParam::SetOrigin(origin.unwrap_or_default().to_owned()),
+                prisma_codegen::file::SetParam::SetPageOrigin(page_origin.unwrap_or_default()),
+                prisma_codegen::file::SetParam::SetSize(record.size as i64),
+            ],
+        )
+        .exec()
+        .await
+        .map_err(error_prisma_to_tonic)?;
+
+    Ok((created.id, warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use tonic::Code;
+
+    use crate::events::EventBus;
+    use crate::grpc::chrome_extension::RegisterFilesItem;
+    use crate::storage::{FileStore, MovedFile, Pending};
+    use crate::temp_db::TempDatabase;
+
+    #[test]
+    fn undo_restores_the_replaced_file() -> std::io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("edman-storage-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let source = dir.join("download.txt");
+        let target = dir.join("saved.txt");
+        std::fs::write(&source, "new")?;
+        std::fs::write(&target, "old")?;
+
+        let moved = MovedFile::move_in(&source, &target)?;
+        assert_eq!(std::fs::read_to_string(&target)?, "new");
+        moved.undo()?;
+
+        assert_eq!(std::fs::read_to_string(&source)?, "new");
+        assert_eq!(std::fs::read_to_string(&target)?, "old");
+        assert_eq!(std::fs::read_dir(&dir)?.count(), 2);
+
+        std::fs::remove_dir_all(dir)
+    }
+
+    #[test]
+    fn skipped_download_is_kept_on_roll_back() -> std::io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("edman-pending-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let source = dir.join("download.txt");
+        std::fs::write(&source, "new")?;
+
+        Pending::Delete(source.to_owned()).roll_back()?;
+        assert!(source.exists());
+        Pending::Delete(source.to_owned()).commit()?;
+        assert!(!source.exists());
+
+        std::fs::remove_dir_all(dir)
+    }
+
+    #[tokio::test]
+    async fn rejected_file_of_a_batch_is_moved_back() -> std::io::Result<()> {
+        let database = TempDatabase::create().await.unwrap();
+        let dir = std::env::temp_dir().join(format!("edman-store-files-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("downloads/edman"))?;
+        std::fs::write(dir.join("downloads/edman/a.txt"), "abc")?;
+        std::fs::write(dir.join("downloads/edman/b.txt"), "defg")?;
+
+        let mut config = crate::config::default();
+        config.download_directory = dir.join("downloads").to_string_lossy().into_owned();
+        config.save_file_directory = dir.join("saved").to_string_lossy().into_owned();
+        config.save_quota_bytes = 4;
+        let files: Vec<_> = ["a", "b"]
+            .into_iter()
+            .map(|name| RegisterFilesItem {
+                download_path: format!("edman/{}.txt", name),
+                save_path: vec![format!("{}.txt", name)],
+                key: name.to_string(),
+                page_origin: None,
+            })
+            .collect();
+
+        let store = FileStore::new(database.client.clone(), EventBus::default());
+        let results = store
+            .store_files(&config, "default", None, &files)
+            .await
+            .unwrap();
+
+        assert!(results[0].as_ref().unwrap().id.is_some());
+        assert_eq!(
+            results[1].as_ref().unwrap_err().code(),
+            Code::ResourceExhausted
+        );
+        assert!(dir.join("saved/a.txt").exists());
+        assert!(!dir.join("saved/b.txt").exists());
+        assert!(dir.join("downloads/edman/b.txt").exists());
+
+        std::fs::remove_dir_all(dir)
+    }
+}
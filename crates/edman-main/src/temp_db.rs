@@ -0,0 +1,42 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use prisma_codegen::PrismaClient;
+
+const FILE_NAME: &str = "edman.db";
+
+/// Migrated database in a directory of its own, which is deleted when it is dropped
+pub(crate) struct TempDatabase {
+    pub client: Arc<PrismaClient>,
+    dir: PathBuf,
+}
+
+impl TempDatabase {
+    pub async fn create() -> Result<Self, Box<dyn std::error::Error>> {
+        static CREATED: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "edman-db-{}-{}",
+            std::process::id(),
+            CREATED.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir)?;
+
+        let db_url = format!("file:{}", dir.join(FILE_NAME).display());
+        let client = Arc::new(PrismaClient::_builder().with_url(db_url).build().await?);
+        client._migrate_deploy().await?;
+
+        Ok(Self { client, dir })
+    }
+
+    #[cfg(test)]
+    pub fn path(&self) -> PathBuf {
+        self.dir.join(FILE_NAME)
+    }
+}
+
+impl Drop for TempDatabase {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
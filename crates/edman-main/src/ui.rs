@@ -1,49 +1,90 @@
+use std::pin::Pin;
 use std::sync::Arc;
 
 use prisma_codegen::PrismaClient;
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
 
 use crate::error_prisma_to_tonic;
+use crate::events::EventBus;
+use crate::manifests;
+use crate::progress::ProgressTracker;
+use crate::queue::Queue;
 use crate::{
-    config::ConfigurationInterface,
+    config::{self, file::FileConfig, ConfigurationInterface},
     grpc::ui::{self, edman_main_server::EdmanMain},
 };
 
 use tonic::{Request, Response, Status};
 
+#[derive(Clone)]
 pub struct UiInterface {
     pub prisma_client: Arc<PrismaClient>,
+    pub file_config: Arc<FileConfig>,
+    pub events: EventBus,
+    pub queue: Queue,
+    pub progress: ProgressTracker,
+    /// Serves clients on other machines, which must not choose paths on this one
+    pub remote: bool,
 }
 
 // TODO: Rust 1.74
 #[tonic::async_trait]
 impl EdmanMain for UiInterface {
+    type WatchEventsStream = Pin<Box<dyn Stream<Item = Result<ui::Event, Status>> + Send>>;
+    type WatchProgressStream =
+        Pin<Box<dyn Stream<Item = Result<ui::DownloadProgress, Status>> + Send>>;
+
     async fn get_config(
         &self,
         _request: Request<ui::ConfigRequest>,
     ) -> Result<Response<ui::ConfigReply>, Status> {
-        let config = crate::config::Config::ensure_db(&self.prisma_client)
+        let profile = crate::config::active_profile(&self.prisma_client, &self.file_config)
             .await
             .map_err(error_prisma_to_tonic)?;
+        let config =
+            crate::config::resolve(&self.prisma_client, &self.file_config, &profile).await?;
 
         Ok(Response::new(ui::ConfigReply {
             config: Some(*config),
+            profile,
         }))
     }
     async fn set_config(
         &self,
         request: Request<ui::UpdateConfigRequest>,
     ) -> Result<Response<ui::UpdateConfigReply>, Status> {
+        self.refuse_remote()?;
+        let profile = crate::config::active_profile(&self.prisma_client, &self.file_config)
+            .await
+            .map_err(error_prisma_to_tonic)?;
+        let allow_lists_before = manifests::allow_lists(
+            &crate::config::list_profiles(&self.prisma_client, &self.file_config)
+                .await
+                .map_err(error_prisma_to_tonic)?,
+        );
         if let Some(ref req_config) = request.get_ref().config {
-            crate::config::Config::update_db(&self.prisma_client, req_config.to_owned())
+            crate::config::Config::update_db(&self.prisma_client, &profile, req_config.to_owned())
                 .await
                 .map_err(error_prisma_to_tonic)?;
         };
-        let config = crate::config::Config::ensure_db(&self.prisma_client)
+        let config =
+            crate::config::resolve(&self.prisma_client, &self.file_config, &profile).await?;
+
+        // Installed manifests only need to change along with the allow-lists
+        let profiles = crate::config::list_profiles(&self.prisma_client, &self.file_config)
             .await
             .map_err(error_prisma_to_tonic)?;
+        let manifests = if manifests::allow_lists(&profiles) != allow_lists_before {
+            manifests::rewrite_all(&self.prisma_client, &profiles)
+                .await
+                .map_err(error_prisma_to_tonic)?
+        } else {
+            vec![]
+        };
 
         Ok(Response::new(ui::UpdateConfigReply {
             config: Some(*config),
+            manifests,
         }))
     }
 
@@ -67,8 +108,159 @@ impl EdmanMain for UiInterface {
                     created_at: file.created_at.timestamp(),
                     key: file.key.to_owned(),
                     path: file.path.to_owned(),
+                    profile: file.profile.to_owned(),
                 })
                 .collect(),
         }))
     }
+
+    async fn list_profiles(
+        &self,
+        _request: Request<ui::ListProfilesRequest>,
+    ) -> Result<Response<ui::ListProfilesReply>, Status> {
+        let profiles = crate::config::list_profiles(&self.prisma_client, &self.file_config)
+            .await
+            .map_err(error_prisma_to_tonic)?;
+
+        Ok(Response::new(ui::ListProfilesReply { profiles }))
+    }
+
+    async fn create_profile(
+        &self,
+        request: Request<ui::CreateProfileRequest>,
+    ) -> Result<Response<ui::CreateProfileReply>, Status> {
+        let params = request.get_ref();
+        if params.name.is_empty() {
+            return Err(Status::invalid_argument("Profile name must not be empty"));
+        }
+
+        let config = match params.config {
+            Some(ref config) => {
+                self.refuse_remote()?;
+                config.to_owned()
+            }
+            None => {
+                let active = crate::config::active_profile(&self.prisma_client, &self.file_config)
+                    .await
+                    .map_err(error_prisma_to_tonic)?;
+                crate::config::Config::read_db(&self.prisma_client, &active)
+                    .await
+                    .map_err(error_prisma_to_tonic)?
+                    .map_or_else(config::default, |config| *config)
+            }
+        };
+        crate::config::create_profile(&self.prisma_client, &params.name, config)
+            .await
+            .map_err(error_prisma_to_tonic)?;
+
+        let profile = self.find_profile(&params.name).await?;
+        Ok(Response::new(ui::CreateProfileReply {
+            profile: Some(profile),
+        }))
+    }
+
+    async fn switch_profile(
+        &self,
+        request: Request<ui::SwitchProfileRequest>,
+    ) -> Result<Response<ui::SwitchProfileReply>, Status> {
+        let name = &request.get_ref().name;
+        if let Some(ref pinned) = self.file_config.active_profile {
+            if pinned != name {
+                return Err(Status::failed_precondition(format!(
+                    "The active profile is pinned to \"{}\" by {}",
+                    pinned,
+                    utils::config_file_path().display()
+                )));
+            }
+        }
+
+        crate::config::switch_profile(&self.prisma_client, &self.file_config, name).await?;
+
+        let profile = self.find_profile(name).await?;
+        Ok(Response::new(ui::SwitchProfileReply {
+            profile: Some(profile),
+        }))
+    }
+
+    async fn watch_events(
+        &self,
+        _request: Request<ui::WatchEventsRequest>,
+    ) -> Result<Response<Self::WatchEventsStream>, Status> {
+        // Events missed by a lagging subscriber are dropped
+        let stream = BroadcastStream::new(self.events.subscribe())
+            .filter_map(|event| event.ok())
+            .map(Ok);
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn list_jobs(
+        &self,
+        _request: Request<ui::ListJobsRequest>,
+    ) -> Result<Response<ui::ListJobsReply>, Status> {
+        let jobs = self.queue.list().await?;
+        Ok(Response::new(ui::ListJobsReply { jobs }))
+    }
+
+    async fn pause_job(
+        &self,
+        request: Request<ui::JobActionRequest>,
+    ) -> Result<Response<ui::JobActionReply>, Status> {
+        let job = self.queue.pause(request.get_ref().id).await?;
+        Ok(Response::new(ui::JobActionReply { job: Some(job) }))
+    }
+
+    async fn resume_job(
+        &self,
+        request: Request<ui::JobActionRequest>,
+    ) -> Result<Response<ui::JobActionReply>, Status> {
+        let job = self.queue.resume(request.get_ref().id).await?;
+        Ok(Response::new(ui::JobActionReply { job: Some(job) }))
+    }
+
+    async fn cancel_job(
+        &self,
+        request: Request<ui::JobActionRequest>,
+    ) -> Result<Response<ui::JobActionReply>, Status> {
+        let job = self.queue.cancel(request.get_ref().id).await?;
+        Ok(Response::new(ui::JobActionReply { job: Some(job) }))
+    }
+
+    async fn retry_job(
+        &self,
+        request: Request<ui::JobActionRequest>,
+    ) -> Result<Response<ui::JobActionReply>, Status> {
+        let job = self.queue.retry(request.get_ref().id).await?;
+        Ok(Response::new(ui::JobActionReply { job: Some(job) }))
+    }
+
+    async fn watch_progress(
+        &self,
+        _request: Request<ui::WatchProgressRequest>,
+    ) -> Result<Response<Self::WatchProgressStream>, Status> {
+        let stream = self.progress.watch().map(Ok);
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+impl UiInterface {
+    /// Rejects requests that set directories, if they come from another machine.
+    fn refuse_remote(&self) -> Result<(), Status> {
+        if self.remote {
+            return Err(Status::permission_denied(
+                "The configuration can only be changed on the machine that runs edman-main",
+            ));
+        }
+        Ok(())
+    }
+
+    async fn find_profile(&self, name: &str) -> Result<config::Profile, Status> {
+        crate::config::list_profiles(&self.prisma_client, &self.file_config)
+            .await
+            .map_err(error_prisma_to_tonic)?
+            .into_iter()
+            .find(|profile| profile.name == name)
+            .ok_or_else(|| Status::not_found(format!("Profile \"{}\" not found", name)))
+    }
 }
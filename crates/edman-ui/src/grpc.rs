@@ -10,4 +10,4 @@ pub mod ui {
     tonic::include_proto!("ui");
 }
 
-pub type Client = EdmanMainClient<tonic::transport::Channel>;
+pub type Client = EdmanMainClient<transport::Channel>;
@@ -5,7 +5,7 @@ use iced::{
     widget::{column, row, text},
     Command, Element,
 };
-use tonic::transport::Channel;
+use transport::Channel;
 
 #[derive(Default)]
 pub struct Loading {
@@ -16,7 +16,7 @@ pub struct Loading {
 pub enum LoadingMessage {
     Font(Result<(), font::Error>),
     Icon(Result<(), font::Error>),
-    ApiServer(Result<Channel, tonic::transport::Error>),
+    ApiServer(Result<Channel, transport::LaunchError>),
     Ui,
 }
 
@@ -29,7 +29,10 @@ impl Loading {
                     .map(LoadingMessage::Font),
                 font::load(include_bytes!("../fonts/Font Awesome 6 Free-Solid-900.otf").as_slice())
                     .map(LoadingMessage::Icon),
-                Command::perform(transport::connect(), LoadingMessage::ApiServer),
+                Command::perform(
+                    transport::connect_remote_or_launch(),
+                    LoadingMessage::ApiServer,
+                ),
             ]),
         )
     }
@@ -76,7 +79,7 @@ macro_rules! map {
 pub struct LoadingStates {
     pub font: Option<Result<(), font::Error>>,
     pub icon: Option<Result<(), font::Error>>,
-    pub api_server: Option<Result<Channel, tonic::transport::Error>>,
+    pub api_server: Option<Result<Channel, transport::LaunchError>>,
 }
 
 impl LoadingStates {
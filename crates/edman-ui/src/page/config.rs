@@ -1,25 +1,55 @@
 use iced::{
-    widget::{button, column, row, text, text_input},
+    widget::{button, column, pick_list, row, text, text_input},
     Command, Element,
 };
 use tonic::Request;
 
-use crate::grpc;
+use crate::grpc::{self, config::CollisionPolicy};
+
+const COLLISION_POLICIES: [CollisionPolicy; 5] = [
+    CollisionPolicy::Overwrite,
+    CollisionPolicy::AutoSuffix,
+    CollisionPolicy::HashSuffix,
+    CollisionPolicy::Skip,
+    CollisionPolicy::Fail,
+];
+
+impl std::fmt::Display for CollisionPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            CollisionPolicy::Overwrite => "Overwrite",
+            CollisionPolicy::AutoSuffix => "Add number",
+            CollisionPolicy::HashSuffix => "Add hash",
+            CollisionPolicy::Skip => "Skip",
+            CollisionPolicy::Fail => "Fail",
+        };
+        f.write_str(name)
+    }
+}
 
 pub struct ConfigSettings {
-    channel: tonic::transport::Channel,
+    channel: transport::Channel,
 
     config: Option<grpc::config::Config>,
     allowed_origin: String,
     allowed_extension: String,
+
+    profiles: Vec<grpc::config::Profile>,
+    new_profile: String,
 }
 
 #[derive(Debug, Clone)]
 pub enum ConfigSettingsMessage {
     Loaded(Result<Option<grpc::config::Config>, tonic::Status>),
+    ProfilesLoaded(Result<Vec<grpc::config::Profile>, tonic::Status>),
 
     StartUpdate,
 
+    SwitchProfile(String),
+    NewProfileChange(String),
+    CreateProfile,
+    ProfileChanged(Result<(), tonic::Status>),
+
     ConfigUpdate(ConfigUpdateMessage),
 
     ChromiumOriginChange(String),
@@ -31,6 +61,8 @@ pub enum ConfigUpdateMessage {
     DownloadDirectoryChange(String),
     DownloadSubDirectoryChange(String),
     SaveFileDirectoryChange(String),
+    CollisionPolicyChange(CollisionPolicy),
+    SaveQuotaChange(String),
 
     ChromiumOriginAdd,
     FirefoxExtensionAdd,
@@ -39,19 +71,31 @@ pub enum ConfigUpdateMessage {
 }
 
 impl ConfigSettings {
-    pub fn new(channel: tonic::transport::Channel) -> (Self, Command<ConfigSettingsMessage>) {
+    pub fn new(channel: transport::Channel) -> (Self, Command<ConfigSettingsMessage>) {
         (
             Self {
                 channel: channel.clone(),
                 config: None,
                 allowed_origin: String::new(),
                 allowed_extension: String::new(),
+                profiles: vec![],
+                new_profile: String::new(),
             },
+            Self::reload(channel),
+        )
+    }
+
+    fn reload(channel: transport::Channel) -> Command<ConfigSettingsMessage> {
+        Command::batch(vec![
             Command::perform(
-                Self::fetch_config(channel),
+                Self::fetch_config(channel.clone()),
                 ConfigSettingsMessage::Loaded,
             ),
-        )
+            Command::perform(
+                Self::fetch_profiles(channel),
+                ConfigSettingsMessage::ProfilesLoaded,
+            ),
+        ])
     }
 
     pub fn view(&self) -> Element<ConfigSettingsMessage> {
@@ -71,10 +115,41 @@ impl ConfigSettings {
             "Save",
             text_input("save dir", &config.save_file_directory[..])
                 .on_input(ConfigUpdateMessage::SaveFileDirectoryChange),
+            "On collision",
+            pick_list(
+                &COLLISION_POLICIES[..],
+                Some(config.collision_policy()),
+                ConfigUpdateMessage::CollisionPolicyChange
+            ),
+            "Quota (bytes, 0 for unlimited)",
+            text_input("save quota", &config.save_quota_bytes.to_string())
+                .on_input(ConfigUpdateMessage::SaveQuotaChange),
         ]
         .into();
 
+        let profile_names: Vec<String> = self
+            .profiles
+            .iter()
+            .map(|profile| profile.name.to_owned())
+            .collect();
+        let active_profile = self
+            .profiles
+            .iter()
+            .find(|profile| profile.active)
+            .map(|profile| profile.name.to_owned());
+
         column![
+            "Profile",
+            row![
+                pick_list(
+                    profile_names,
+                    active_profile,
+                    ConfigSettingsMessage::SwitchProfile
+                ),
+                text_input("new profile", &self.new_profile[..])
+                    .on_input(ConfigSettingsMessage::NewProfileChange),
+                button("+").on_press(ConfigSettingsMessage::CreateProfile),
+            ],
             directory_settings.map(ConfigSettingsMessage::ConfigUpdate),
             "Ext[Cr]",
             row![
@@ -132,6 +207,30 @@ impl ConfigSettings {
                 Err(err) => eprintln!("{}", err),
             },
 
+            ConfigSettingsMessage::ProfilesLoaded(result) => match result {
+                Ok(profiles) => self.profiles = profiles,
+                Err(err) => eprintln!("{}", err),
+            },
+
+            ConfigSettingsMessage::SwitchProfile(name) => {
+                return Command::perform(
+                    Self::switch_profile(self.channel.clone(), name),
+                    ConfigSettingsMessage::ProfileChanged,
+                )
+            }
+            ConfigSettingsMessage::NewProfileChange(s) => self.new_profile = s,
+            ConfigSettingsMessage::CreateProfile => {
+                let name = std::mem::take(&mut self.new_profile);
+                return Command::perform(
+                    Self::create_profile(self.channel.clone(), name),
+                    ConfigSettingsMessage::ProfileChanged,
+                );
+            }
+            ConfigSettingsMessage::ProfileChanged(result) => match result {
+                Ok(()) => return Self::reload(self.channel.clone()),
+                Err(err) => eprintln!("{}", err),
+            },
+
             ConfigSettingsMessage::ChromiumOriginChange(s) => self.allowed_origin = s,
             ConfigSettingsMessage::FirefoxExtensionChange(s) => self.allowed_extension = s,
 
@@ -155,6 +254,16 @@ impl ConfigSettings {
                     ConfigUpdateMessage::SaveFileDirectoryChange(s) => {
                         config.save_file_directory = s;
                     }
+                    ConfigUpdateMessage::CollisionPolicyChange(policy) => {
+                        config.set_collision_policy(policy);
+                    }
+                    ConfigUpdateMessage::SaveQuotaChange(s) => {
+                        if s.is_empty() {
+                            config.save_quota_bytes = 0;
+                        } else if let Ok(bytes) = s.parse() {
+                            config.save_quota_bytes = bytes;
+                        }
+                    }
 
                     ConfigUpdateMessage::ChromiumOriginAdd => {
                         config.allowed_origins.push(self.allowed_origin.to_owned());
@@ -179,7 +288,7 @@ impl ConfigSettings {
     }
 
     async fn fetch_config(
-        channel: tonic::transport::Channel,
+        channel: transport::Channel,
     ) -> Result<Option<grpc::config::Config>, tonic::Status> {
         let mut client = grpc::Client::new(channel.clone());
         let response = client
@@ -189,7 +298,7 @@ impl ConfigSettings {
     }
 
     async fn update_config(
-        channel: tonic::transport::Channel,
+        channel: transport::Channel,
         config: Option<grpc::config::Config>,
     ) -> Result<Option<grpc::config::Config>, tonic::Status> {
         let mut client = grpc::Client::new(channel.clone());
@@ -198,4 +307,39 @@ impl ConfigSettings {
             .await?;
         Ok(response.get_ref().config.to_owned())
     }
+
+    async fn fetch_profiles(
+        channel: transport::Channel,
+    ) -> Result<Vec<grpc::config::Profile>, tonic::Status> {
+        let mut client = grpc::Client::new(channel);
+        let response = client
+            .list_profiles(Request::new(grpc::ui::ListProfilesRequest {}))
+            .await?;
+        Ok(response.into_inner().profiles)
+    }
+
+    async fn switch_profile(
+        channel: transport::Channel,
+        name: String,
+    ) -> Result<(), tonic::Status> {
+        let mut client = grpc::Client::new(channel);
+        client
+            .switch_profile(Request::new(grpc::ui::SwitchProfileRequest { name }))
+            .await?;
+        Ok(())
+    }
+
+    async fn create_profile(
+        channel: transport::Channel,
+        name: String,
+    ) -> Result<(), tonic::Status> {
+        let mut client = grpc::Client::new(channel);
+        client
+            .create_profile(Request::new(grpc::ui::CreateProfileRequest {
+                name,
+                config: None,
+            }))
+            .await?;
+        Ok(())
+    }
 }
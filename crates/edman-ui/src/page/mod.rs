@@ -19,7 +19,7 @@ pub enum PageMessage {
 }
 
 impl Page {
-    pub fn new(grpc_channel: tonic::transport::Channel) -> (Self, Command<PageMessage>) {
+    pub fn new(grpc_channel: transport::Channel) -> (Self, Command<PageMessage>) {
         let (file_view, file_view_command) = FileView::new(grpc_channel.clone());
         let (configs, config_command) = ConfigSettings::new(grpc_channel);
         (
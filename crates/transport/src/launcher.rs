@@ -0,0 +1,243 @@
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, SystemTime};
+
+use tokio::time::Instant;
+use tonic::transport::Channel;
+
+use crate::tcp::{BearerToken, Remote, RemoteError};
+
+/// Environment variable that overrides where the edman-main binary is
+pub const MAIN_PATH_VAR: &str = "EDMAN_MAIN_PATH";
+
+/// How long a freshly started edman-main may take until it listens
+const LAUNCH_TIMEOUT: Duration = Duration::from_secs(10);
+/// Interval between connection attempts while edman-main starts
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug)]
+pub enum LaunchError {
+    /// There is no edman-main binary at this path
+    NotFound(PathBuf),
+    Io(std::io::Error),
+    /// edman-main was started, but did not listen in time
+    Timeout(tonic::transport::Error),
+    /// The remote edman-main cannot be used
+    Remote(RemoteError),
+}
+
+impl std::fmt::Display for LaunchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LaunchError::NotFound(path) => write!(
+                f,
+                "edman-main is not running and was not found at {}. Set {} to its path.",
+                path.display(),
+                MAIN_PATH_VAR
+            ),
+            LaunchError::Io(err) => write!(f, "Cannot start edman-main: {}", err),
+            LaunchError::Timeout(err) => write!(
+                f,
+                "edman-main did not start listening within {} seconds: {}",
+                LAUNCH_TIMEOUT.as_secs(),
+                err
+            ),
+            LaunchError::Remote(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for LaunchError {}
+
+impl From<std::io::Error> for LaunchError {
+    fn from(err: std::io::Error) -> Self {
+        LaunchError::Io(err)
+    }
+}
+
+/// Connects to edman-main, starting it first if nobody is listening.
+///
+/// Processes that race to start it agree through a lock file: the one that creates it
+/// spawns edman-main, the others only wait for the socket.
+pub async fn connect_or_launch() -> Result<Channel, LaunchError> {
+    if let Ok(channel) = crate::connect().await {
+        return Ok(channel);
+    }
+
+    let lock_path = utils::launch_lock_path();
+    utils::create_parent_dirs(&lock_path)?;
+    let lock = LaunchLock::acquire(&lock_path)?;
+    if lock.is_some() {
+        spawn_main()?;
+    }
+
+    wait_for_socket().await
+}
+
+/// Connects to the edman-main in [`crate::tcp::REMOTE_VAR`] if it is set, and to the local
+/// one like [`connect_or_launch`] otherwise.
+pub async fn connect_remote_or_launch() -> Result<crate::Channel, LaunchError> {
+    match Remote::from_env().map_err(LaunchError::Remote)? {
+        Some(remote) => remote.connect().await.map_err(LaunchError::Remote),
+        None => Ok(crate::Channel::new(
+            connect_or_launch().await?,
+            BearerToken::default(),
+        )),
+    }
+}
+
+async fn wait_for_socket() -> Result<Channel, LaunchError> {
+    let deadline = Instant::now() + LAUNCH_TIMEOUT;
+    loop {
+        match crate::connect().await {
+            Ok(channel) => return Ok(channel),
+            Err(err) if Instant::now() >= deadline => return Err(LaunchError::Timeout(err)),
+            Err(_) => tokio::time::sleep(POLL_INTERVAL).await,
+        }
+    }
+}
+
+fn main_binary() -> Result<PathBuf, LaunchError> {
+    let path = match std::env::var_os(MAIN_PATH_VAR) {
+        Some(path) => PathBuf::from(path),
+        None => std::env::current_exe()?
+            .with_file_name(format!("edman-main{}", std::env::consts::EXE_SUFFIX)),
+    };
+
+    if !path.is_file() {
+        return Err(LaunchError::NotFound(path));
+    }
+    Ok(path)
+}
+
+fn spawn_main() -> Result<(), LaunchError> {
+    let mut command = std::process::Command::new(main_binary()?);
+    // The stdio of the native host carries native messages
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            use std::os::unix::process::CommandExt;
+            // Keep running when the browser kills the native host along with its group
+            command.process_group(0);
+        } else if #[cfg(windows)] {
+            use std::os::windows::process::CommandExt;
+            const DETACHED_PROCESS: u32 = 0x0000_0008;
+            const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+            command.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP);
+        }
+    }
+
+    let mut child = command.spawn()?;
+    // Reaped as soon as it exits, so that it does not linger as a zombie while we run
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+/// Removes the lock file when dropped
+struct LaunchLock<'a> {
+    path: &'a Path,
+}
+
+impl<'a> LaunchLock<'a> {
+    /// Returns `None` if another process is starting edman-main.
+    fn acquire(path: &'a Path) -> std::io::Result<Option<Self>> {
+        for _ in 0..2 {
+            match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(path)
+            {
+                Ok(_) => return Ok(Some(Self { path })),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                    if !is_stale(path) {
+                        return Ok(None);
+                    }
+                    // Left behind by a process that died while starting edman-main
+                    let _ = std::fs::remove_file(path);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl Drop for LaunchLock<'_> {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(self.path);
+    }
+}
+
+fn is_stale(path: &Path) -> bool {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .map_or(false, |age| age > LAUNCH_TIMEOUT)
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::Duration;
+
+    use tonic::transport::server::Routes;
+
+    use crate::launcher::{connect_or_launch, MAIN_PATH_VAR};
+
+    /// Set for the test binary when it runs as the stub of edman-main
+    const STUB_VAR: &str = "EDMAN_LAUNCHER_STUB";
+
+    /// Listens at the socket like edman-main, but only when started by [`launches_main`].
+    #[tokio::test]
+    async fn stub_main() {
+        if std::env::var_os(STUB_VAR).is_none() {
+            return;
+        }
+        // Give the launcher a chance to find the socket missing more than once
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let incoming = crate::sock_stream().await.unwrap();
+        let serve = tonic::transport::Server::builder()
+            .add_routes(Routes::default())
+            .serve_with_incoming(incoming);
+        let _ = tokio::time::timeout(Duration::from_secs(5), serve).await;
+    }
+
+    #[tokio::test]
+    async fn launches_main() -> Result<(), Box<dyn std::error::Error>> {
+        if std::env::var_os(STUB_VAR).is_some() {
+            return Ok(());
+        }
+        let dir = std::env::temp_dir().join(format!("edman-launcher-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        // Keeps the socket and the lock file of a running edman-main out of the way
+        std::env::set_var("XDG_RUNTIME_DIR", dir.join("runtime"));
+        std::env::set_var("XDG_CACHE_HOME", dir.join("cache"));
+
+        let stub = dir.join("edman-main");
+        std::fs::write(
+            &stub,
+            format!(
+                "#!/bin/sh\n{}=1 exec \"{}\" launcher::tests::stub_main --exact\n",
+                STUB_VAR,
+                std::env::current_exe()?.display()
+            ),
+        )?;
+        std::fs::set_permissions(&stub, std::fs::Permissions::from_mode(0o755))?;
+        std::env::set_var(MAIN_PATH_VAR, &stub);
+
+        assert!(!utils::sock_path().exists());
+        connect_or_launch().await?;
+        assert!(utils::sock_path().exists());
+        assert!(!utils::launch_lock_path().exists());
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
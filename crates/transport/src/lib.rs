@@ -1,3 +1,8 @@
+mod launcher;
+pub use launcher::{connect_or_launch, connect_remote_or_launch, LaunchError, MAIN_PATH_VAR};
+pub mod tcp;
+pub use tcp::Channel;
+
 cfg_if::cfg_if! {
     if #[cfg(unix)] {
         mod unix;
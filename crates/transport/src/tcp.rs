@@ -0,0 +1,289 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::service::{interceptor::InterceptedService, Interceptor};
+use tonic::transport::{
+    Certificate, ClientTlsConfig, Endpoint, Identity, Server, ServerTlsConfig, Uri,
+};
+use tonic::{Request, Status};
+
+/// Environment variable with the `https://` URL of a remote edman-main
+pub const REMOTE_VAR: &str = "EDMAN_REMOTE";
+/// Environment variable with the token for the remote edman-main
+pub const TOKEN_VAR: &str = "EDMAN_TOKEN";
+/// Environment variable with a PEM file of the CA that signed the certificate of the remote
+/// edman-main, e.g. the certificate itself if it is self-signed
+pub const CA_VAR: &str = "EDMAN_TLS_CA";
+
+/// Channel to edman-main, which carries the token if edman-main is remote
+pub type Channel = InterceptedService<tonic::transport::Channel, BearerToken>;
+
+/// Where edman-main listens for connections from other machines.
+///
+/// It only serves edman-ui there, without the requests that choose paths on the machine.
+pub struct TcpOptions {
+    pub addr: SocketAddr,
+    /// PEM certificate chain presented to clients
+    pub cert: PathBuf,
+    /// PEM private key of `cert`
+    pub key: PathBuf,
+    /// Clients have to send it as `authorization: Bearer <token>`
+    pub token: String,
+}
+
+impl TcpOptions {
+    /// Server that only accepts TLS connections with the certificate of these options.
+    ///
+    /// Add [`RequireToken`] to it as a layer, so that requests without the token are rejected.
+    pub fn tls_server(&self) -> Result<Server, Box<dyn std::error::Error>> {
+        let cert = std::fs::read(&self.cert)
+            .map_err(|err| format!("Cannot read {}: {}", self.cert.display(), err))?;
+        let key = std::fs::read(&self.key)
+            .map_err(|err| format!("Cannot read {}: {}", self.key.display(), err))?;
+        let tls = ServerTlsConfig::new().identity(Identity::from_pem(cert, key));
+
+        Ok(Server::builder().tls_config(tls)?)
+    }
+}
+
+/// Rejects requests that do not carry the token of the server
+#[derive(Clone)]
+pub struct RequireToken {
+    expected: MetadataValue<Ascii>,
+}
+
+impl RequireToken {
+    pub fn new(token: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        if token.is_empty() {
+            return Err("The token must not be empty".into());
+        }
+        Ok(Self {
+            expected: bearer(token)?,
+        })
+    }
+}
+
+impl Interceptor for RequireToken {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        match request.metadata().get("authorization") {
+            Some(value) if constant_time_eq(value.as_bytes(), self.expected.as_bytes()) => {
+                Ok(request)
+            }
+            _ => Err(Status::unauthenticated("Missing or wrong token")),
+        }
+    }
+}
+
+/// Sends the token along with every request, if there is one
+#[derive(Clone, Default)]
+pub struct BearerToken {
+    value: Option<MetadataValue<Ascii>>,
+}
+
+impl BearerToken {
+    pub fn new(token: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            value: Some(bearer(token)?),
+        })
+    }
+}
+
+impl Interceptor for BearerToken {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(value) = &self.value {
+            request
+                .metadata_mut()
+                .insert("authorization", value.to_owned());
+        }
+        Ok(request)
+    }
+}
+
+fn bearer(token: &str) -> Result<MetadataValue<Ascii>, Box<dyn std::error::Error>> {
+    format!("Bearer {}", token)
+        .parse()
+        .map_err(|_| "The token may only contain visible ASCII characters".into())
+}
+
+/// Compares without returning early, so that the time taken does not reveal the token
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[derive(Debug)]
+pub enum RemoteError {
+    /// The environment variables do not describe a usable remote
+    Config(String),
+    Io(std::io::Error),
+    Connect(tonic::transport::Error),
+}
+
+impl std::fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RemoteError::Config(message) => write!(f, "{}", message),
+            RemoteError::Io(err) => write!(f, "Cannot read {}: {}", CA_VAR, err),
+            RemoteError::Connect(err) => {
+                write!(
+                    f,
+                    "Cannot connect to the edman-main in {}: {}",
+                    REMOTE_VAR, err
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for RemoteError {}
+
+impl From<std::io::Error> for RemoteError {
+    fn from(err: std::io::Error) -> Self {
+        RemoteError::Io(err)
+    }
+}
+
+impl From<tonic::transport::Error> for RemoteError {
+    fn from(err: tonic::transport::Error) -> Self {
+        RemoteError::Connect(err)
+    }
+}
+
+/// edman-main on another machine, as configured in the environment
+pub struct Remote {
+    pub uri: Uri,
+    pub token: String,
+    /// Trusted in addition to the usual root certificates
+    pub ca: Option<PathBuf>,
+}
+
+impl Remote {
+    /// Returns `None` if [`REMOTE_VAR`] is not set.
+    pub fn from_env() -> Result<Option<Self>, RemoteError> {
+        let Ok(remote) = std::env::var(REMOTE_VAR) else {
+            return Ok(None);
+        };
+        let uri: Uri = remote
+            .parse()
+            .map_err(|err| RemoteError::Config(format!("{} is not a URL: {}", REMOTE_VAR, err)))?;
+        if uri.scheme_str() != Some("https") {
+            return Err(RemoteError::Config(format!(
+                "{} must be an https:// URL",
+                REMOTE_VAR
+            )));
+        }
+        let token = std::env::var(TOKEN_VAR).map_err(|_| {
+            RemoteError::Config(format!(
+                "{} has to be set along with {}",
+                TOKEN_VAR, REMOTE_VAR
+            ))
+        })?;
+
+        Ok(Some(Self {
+            uri,
+            token,
+            ca: std::env::var_os(CA_VAR).map(PathBuf::from),
+        }))
+    }
+
+    pub async fn connect(&self) -> Result<Channel, RemoteError> {
+        let mut tls = ClientTlsConfig::new();
+        if let Some(ca) = &self.ca {
+            tls = tls.ca_certificate(Certificate::from_pem(std::fs::read(ca)?));
+        }
+        let token = BearerToken::new(&self.token)
+            .map_err(|err| RemoteError::Config(format!("{}: {}", TOKEN_VAR, err)))?;
+
+        let channel = Endpoint::from(self.uri.to_owned())
+            .tls_config(tls)?
+            .connect()
+            .await?;
+        Ok(Channel::new(channel, token))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use tonic::codec::ProstCodec;
+    use tonic::codegen::http::uri::PathAndQuery;
+    use tonic::service::Interceptor;
+    use tonic::transport::server::{Routes, TcpIncoming};
+    use tonic::{Code, Request};
+
+    use crate::tcp::{BearerToken, Remote, RequireToken, TcpOptions};
+
+    #[test]
+    fn token_is_checked() {
+        let mut require = RequireToken::new("secret").unwrap();
+
+        let request = BearerToken::new("secret")
+            .unwrap()
+            .call(Request::new(()))
+            .unwrap();
+        assert!(require.call(request).is_ok());
+
+        for mut client in [BearerToken::new("wrong").unwrap(), BearerToken::default()] {
+            let request = client.call(Request::new(())).unwrap();
+            assert_eq!(
+                require.call(request).unwrap_err().code(),
+                Code::Unauthenticated
+            );
+        }
+    }
+
+    /// Calls a method that no service has, which only gets as far as `Unimplemented`
+    /// if the connection and the token are accepted.
+    async fn call(remote: &Remote) -> Code {
+        let channel = remote.connect().await.unwrap();
+        let mut grpc = tonic::client::Grpc::new(channel);
+        grpc.ready().await.unwrap();
+        grpc.unary(
+            Request::new(()),
+            PathAndQuery::from_static("/edman.Test/Call"),
+            ProstCodec::<(), ()>::default(),
+        )
+        .await
+        .unwrap_err()
+        .code()
+    }
+
+    #[tokio::test]
+    async fn tls_listener_with_self_signed_certificate() {
+        let testdata = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("testdata");
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let options = TcpOptions {
+            addr: listener.local_addr().unwrap(),
+            cert: testdata.join("localhost.crt"),
+            key: testdata.join("localhost.key"),
+            token: "secret".to_string(),
+        };
+
+        let require_token = tonic::service::interceptor(RequireToken::new(&options.token).unwrap());
+        let server = options
+            .tls_server()
+            .unwrap()
+            .layer(require_token)
+            .add_routes(Routes::default());
+        let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+        tokio::spawn(server.serve_with_incoming(incoming));
+
+        let remote = |token: &str| Remote {
+            uri: format!("https://localhost:{}", options.addr.port())
+                .parse()
+                .unwrap(),
+            token: token.to_string(),
+            ca: Some(options.cert.to_owned()),
+        };
+        assert_eq!(call(&remote("secret")).await, Code::Unimplemented);
+        assert_eq!(call(&remote("wrong")).await, Code::Unauthenticated);
+
+        // The certificate is not trusted without the CA
+        let untrusted = Remote {
+            ca: None,
+            ..remote("secret")
+        };
+        assert!(untrusted.connect().await.is_err());
+    }
+}
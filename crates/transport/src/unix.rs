@@ -28,7 +28,19 @@ pub async fn sock_stream() -> Result<UnixListenerStream, Box<dyn std::error::Err
     let sock_path = utils::sock_path();
     utils::create_parent_dirs(&sock_path)?;
 
-    let uds = UnixListener::bind(&sock_path)?;
+    let uds = match UnixListener::bind(&sock_path) {
+        Err(err) if err.kind() == std::io::ErrorKind::AddrInUse => {
+            if UnixStream::connect(&sock_path).await.is_ok() {
+                return Err(
+                    format!("edman-main is already listening at {}", sock_path.display()).into(),
+                );
+            }
+            // Left behind by an instance that did not shut down cleanly
+            std::fs::remove_file(&sock_path)?;
+            UnixListener::bind(&sock_path)?
+        }
+        result => result?,
+    };
     let uds_stream = UnixListenerStream::new(uds);
 
     println!("Listening at {}", sock_path.display());
@@ -0,0 +1,85 @@
+/// Browsers that edman-ce-adapter can install its manifest for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum BrowserKind {
+    Chrome,
+    ChromeBeta,
+    ChromeDev,
+    ChromeCanary,
+    Chromium,
+    Edge,
+    Brave,
+    Opera,
+    Vivaldi,
+    Firefox,
+    #[cfg_attr(feature = "clap", value(name = "librewolf"))]
+    LibreWolf,
+    Waterfox,
+}
+
+impl BrowserKind {
+    pub const ALL: [BrowserKind; 12] = [
+        BrowserKind::Chrome,
+        BrowserKind::ChromeBeta,
+        BrowserKind::ChromeDev,
+        BrowserKind::ChromeCanary,
+        BrowserKind::Chromium,
+        BrowserKind::Edge,
+        BrowserKind::Brave,
+        BrowserKind::Opera,
+        BrowserKind::Vivaldi,
+        BrowserKind::Firefox,
+        BrowserKind::LibreWolf,
+        BrowserKind::Waterfox,
+    ];
+
+    /// Same as the value of the command line option
+    pub fn name(&self) -> &'static str {
+        match self {
+            BrowserKind::Chrome => "chrome",
+            BrowserKind::ChromeBeta => "chrome-beta",
+            BrowserKind::ChromeDev => "chrome-dev",
+            BrowserKind::ChromeCanary => "chrome-canary",
+            BrowserKind::Chromium => "chromium",
+            BrowserKind::Edge => "edge",
+            BrowserKind::Brave => "brave",
+            BrowserKind::Opera => "opera",
+            BrowserKind::Vivaldi => "vivaldi",
+            BrowserKind::Firefox => "firefox",
+            BrowserKind::LibreWolf => "librewolf",
+            BrowserKind::Waterfox => "waterfox",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|browser| browser.name() == name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BrowserStrain {
+    Chromium,
+    Firefox,
+}
+
+impl From<&BrowserKind> for BrowserStrain {
+    fn from(value: &BrowserKind) -> Self {
+        match value {
+            BrowserKind::Firefox | BrowserKind::LibreWolf | BrowserKind::Waterfox => Self::Firefox,
+            _ => Self::Chromium,
+        }
+    }
+}
+
+/// Combines the allow-lists of several profiles, so that any of them can launch the host.
+///
+/// Every entry is kept once, in the order it first appears.
+pub fn merge_allow_lists<'a>(lists: impl IntoIterator<Item = &'a [String]>) -> Vec<String> {
+    let mut merged: Vec<String> = vec![];
+    for entry in lists.into_iter().flatten() {
+        if !merged.contains(entry) {
+            merged.push(entry.to_owned());
+        }
+    }
+    merged
+}
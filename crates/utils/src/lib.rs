@@ -2,8 +2,18 @@ use std::path::{Path, PathBuf};
 
 use directories::ProjectDirs;
 
+pub mod browsers;
+pub mod locations;
+
 pub const EDMAN_UNIQUE_NAME: &str = "io.github.femshima.edman";
 
+/// Version of the native messaging protocol between the extension and edman-ce-adapter.
+/// Bump it whenever a message changes in a way older peers cannot handle.
+pub const NATIVE_PROTOCOL_VERSION: u32 = 1;
+
+/// gRPC metadata that edman-main adds to `InvalidArgument` errors about a download or save path
+pub const PATH_INVALID_KEY: &str = "edman-path-invalid";
+
 fn project_dirs() -> ProjectDirs {
     ProjectDirs::from("io.github", "femshima", "edman").expect("Project directory not found")
 }
@@ -37,6 +47,11 @@ pub fn sock_path() -> &'static str {
     r"\\.\pipe\edman"
 }
 
+/// Exists while a process is starting edman-main
+pub fn launch_lock_path() -> PathBuf {
+    project_dirs().cache_dir().join("edman-main.lock")
+}
+
 pub fn manifest_path_firefox() -> PathBuf {
     project_dirs()
         .config_local_dir()
@@ -48,6 +63,15 @@ pub fn manifest_path_chromium() -> PathBuf {
         .join("manifest_chromium.json")
 }
 
+/// Where files are saved unless a profile says otherwise
+pub fn default_save_file_directory() -> PathBuf {
+    project_dirs().data_dir().join("files")
+}
+
+pub fn config_file_path() -> PathBuf {
+    project_dirs().config_dir().join("edman.toml")
+}
+
 pub fn ce_adapter_error_log_path() -> PathBuf {
     std::env::current_exe()
         .ok()
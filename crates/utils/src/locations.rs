@@ -0,0 +1,338 @@
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::browsers::{BrowserKind, BrowserStrain};
+
+/// How a browser is installed, which decides where it looks for manifests
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LocationKind {
+    /// Per-user location of a browser from a regular package
+    User,
+    Flatpak,
+    Snap,
+    /// Read by the browser for every user of the machine
+    System,
+}
+
+impl LocationKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LocationKind::User => "user",
+            LocationKind::Flatpak => "flatpak",
+            LocationKind::Snap => "snap",
+            LocationKind::System => "system",
+        }
+    }
+}
+
+/// Where one installation of a browser looks for the manifest of edman
+pub struct Location {
+    pub kind: LocationKind,
+    /// Exists if the browser is installed or has been used this way
+    pub detect_dir: PathBuf,
+    #[cfg(unix)]
+    pub manifest_path: PathBuf,
+    /// Key whose default value is the path to the manifest
+    #[cfg(windows)]
+    pub registry_key: String,
+}
+
+impl Location {
+    pub fn is_detected(&self) -> bool {
+        self.detect_dir.is_dir()
+    }
+
+    /// Whether both locations register the manifest in the same place, like the registry key
+    /// that Chrome, Opera and Vivaldi all read on Windows.
+    pub fn is_same_registration(&self, other: &Location) -> bool {
+        cfg_if::cfg_if! {
+            if #[cfg(windows)] {
+                self.kind == other.kind && self.registry_key == other.registry_key
+            } else if #[cfg(unix)] {
+                self.manifest_path == other.manifest_path
+            }
+        }
+    }
+}
+
+/// Returns the per-user locations of `browser`, the regular one first, or only
+/// its system-wide location if `system` is set.
+///
+/// Empty if no such location is known for the browser on this platform.
+pub fn locations(browser: &BrowserKind, system: bool) -> Vec<Location> {
+    match directories::BaseDirs::new() {
+        Some(base_dirs) => platform_locations(&base_dirs, browser, system),
+        None => vec![],
+    }
+}
+
+/// File that holds the manifest installed at `location`.
+///
+/// Per-user locations point at the manifest that edman maintains in its own config
+/// directory. Other locations need a copy that sandboxes or other users can read.
+pub fn manifest_file(browser: &BrowserKind, location: &Location) -> std::io::Result<PathBuf> {
+    let own_path = match browser.into() {
+        BrowserStrain::Chromium => crate::manifest_path_chromium(),
+        BrowserStrain::Firefox => crate::manifest_path_firefox(),
+    };
+
+    cfg_if::cfg_if! {
+        if #[cfg(windows)] {
+            if location.kind == LocationKind::System {
+                return Ok(std::env::current_exe()?.with_file_name(own_path.file_name().unwrap()));
+            }
+        } else if #[cfg(unix)] {
+            if location.kind != LocationKind::User {
+                return Ok(location.manifest_path.to_owned());
+            }
+        }
+    }
+    Ok(own_path)
+}
+
+/// Whether installing the manifest for `browser`, per-user or system-wide, may write `path`.
+pub fn is_manifest_file(browser: &BrowserKind, path: &Path) -> bool {
+    [false, true]
+        .into_iter()
+        .flat_map(|system| locations(browser, system))
+        .any(|location| manifest_file(browser, &location).map_or(false, |file| file == path))
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn platform_locations(
+    base_dirs: &directories::BaseDirs,
+    browser: &BrowserKind,
+    system: bool,
+) -> Vec<Location> {
+    if system {
+        // Every channel of Google Chrome reads this, so they are told apart by their packages
+        let chrome = |package_dir: &str| Location {
+            detect_dir: PathBuf::from(package_dir),
+            ..system_location("/etc/opt/chrome/native-messaging-hosts")
+        };
+        let location = match browser {
+            BrowserKind::Chrome => chrome("/opt/google/chrome"),
+            BrowserKind::ChromeBeta => chrome("/opt/google/chrome-beta"),
+            BrowserKind::ChromeDev => chrome("/opt/google/chrome-unstable"),
+            BrowserKind::ChromeCanary => chrome("/opt/google/chrome-canary"),
+            BrowserKind::Chromium => system_location("/etc/chromium/native-messaging-hosts"),
+            BrowserKind::Edge => system_location("/etc/opt/edge/native-messaging-hosts"),
+            BrowserKind::Firefox => system_location("/usr/lib/mozilla/native-messaging-hosts"),
+            // No system-wide location is documented for the others
+            BrowserKind::Brave
+            | BrowserKind::Opera
+            | BrowserKind::Vivaldi
+            | BrowserKind::LibreWolf
+            | BrowserKind::Waterfox => return vec![],
+        };
+        return vec![location];
+    }
+
+    // Relative to the home directory
+    let (profile_dir, manifest_dir) = match browser {
+        BrowserKind::Chrome => (
+            ".config/google-chrome",
+            ".config/google-chrome/NativeMessagingHosts",
+        ),
+        BrowserKind::ChromeBeta => (
+            ".config/google-chrome-beta",
+            ".config/google-chrome-beta/NativeMessagingHosts",
+        ),
+        BrowserKind::ChromeDev => (
+            ".config/google-chrome-unstable",
+            ".config/google-chrome-unstable/NativeMessagingHosts",
+        ),
+        BrowserKind::ChromeCanary => (
+            ".config/google-chrome-canary",
+            ".config/google-chrome-canary/NativeMessagingHosts",
+        ),
+        BrowserKind::Chromium => (".config/chromium", ".config/chromium/NativeMessagingHosts"),
+        BrowserKind::Edge => (
+            ".config/microsoft-edge",
+            ".config/microsoft-edge/NativeMessagingHosts",
+        ),
+        BrowserKind::Brave => (
+            ".config/BraveSoftware/Brave-Browser",
+            ".config/BraveSoftware/Brave-Browser/NativeMessagingHosts",
+        ),
+        BrowserKind::Opera => (".config/opera", ".config/opera/NativeMessagingHosts"),
+        BrowserKind::Vivaldi => (".config/vivaldi", ".config/vivaldi/NativeMessagingHosts"),
+        BrowserKind::Firefox => (".mozilla/firefox", ".mozilla/native-messaging-hosts"),
+        BrowserKind::LibreWolf => (".librewolf", ".librewolf/native-messaging-hosts"),
+        BrowserKind::Waterfox => (".waterfox", ".waterfox/native-messaging-hosts"),
+    };
+    let flatpak_id = match browser {
+        BrowserKind::Chrome => Some("com.google.Chrome"),
+        BrowserKind::ChromeDev => Some("com.google.ChromeDev"),
+        BrowserKind::Chromium => Some("org.chromium.Chromium"),
+        BrowserKind::Edge => Some("com.microsoft.Edge"),
+        BrowserKind::Brave => Some("com.brave.Browser"),
+        BrowserKind::Opera => Some("com.opera.Opera"),
+        BrowserKind::Vivaldi => Some("com.vivaldi.Vivaldi"),
+        BrowserKind::Firefox => Some("org.mozilla.firefox"),
+        BrowserKind::LibreWolf => Some("io.gitlab.librewolf-community"),
+        BrowserKind::Waterfox => Some("net.waterfox.waterfox"),
+        _ => None,
+    };
+    let snap_name = match browser {
+        BrowserKind::Chromium => Some("chromium"),
+        BrowserKind::Brave => Some("brave"),
+        BrowserKind::Opera => Some("opera"),
+        BrowserKind::Firefox => Some("firefox"),
+        _ => None,
+    };
+
+    let home = base_dirs.home_dir();
+    // Sandboxes have a home directory of their own, with a different config directory
+    let sandboxed = |kind, root: PathBuf, config_dir: &str| {
+        let relocate = |path: &str| match path.strip_prefix(".config/") {
+            Some(path) => root.join(config_dir).join(path),
+            None => root.join(path),
+        };
+        Location {
+            kind,
+            detect_dir: relocate(profile_dir),
+            manifest_path: relocate(manifest_dir).join(manifest_file_name()),
+        }
+    };
+
+    let mut locations = vec![Location {
+        kind: LocationKind::User,
+        detect_dir: home.join(profile_dir),
+        manifest_path: home.join(manifest_dir).join(manifest_file_name()),
+    }];
+    if let Some(id) = flatpak_id {
+        let root = home.join(".var/app").join(id);
+        locations.push(sandboxed(LocationKind::Flatpak, root, "config"));
+    }
+    if let Some(name) = snap_name {
+        let root = home.join("snap").join(name).join("common");
+        locations.push(sandboxed(LocationKind::Snap, root, ""));
+    }
+    locations
+}
+
+#[cfg(target_os = "macos")]
+fn platform_locations(
+    base_dirs: &directories::BaseDirs,
+    browser: &BrowserKind,
+    system: bool,
+) -> Vec<Location> {
+    if system {
+        let manifest_dir = match browser {
+            BrowserKind::Chrome => "/Library/Google/Chrome/NativeMessagingHosts",
+            BrowserKind::Chromium => "/Library/Application Support/Chromium/NativeMessagingHosts",
+            BrowserKind::Edge => "/Library/Microsoft/Edge/NativeMessagingHosts",
+            BrowserKind::Firefox => "/Library/Application Support/Mozilla/NativeMessagingHosts",
+            _ => return vec![],
+        };
+        return vec![system_location(manifest_dir)];
+    }
+
+    // Relative to `~/Library/Application Support`
+    let (profile_dir, manifest_dir) = match browser {
+        BrowserKind::Chrome => ("Google/Chrome", "Google/Chrome/NativeMessagingHosts"),
+        BrowserKind::ChromeBeta => (
+            "Google/Chrome Beta",
+            "Google/Chrome Beta/NativeMessagingHosts",
+        ),
+        BrowserKind::ChromeDev => (
+            "Google/Chrome Dev",
+            "Google/Chrome Dev/NativeMessagingHosts",
+        ),
+        BrowserKind::ChromeCanary => (
+            "Google/Chrome Canary",
+            "Google/Chrome Canary/NativeMessagingHosts",
+        ),
+        BrowserKind::Chromium => ("Chromium", "Chromium/NativeMessagingHosts"),
+        BrowserKind::Edge => ("Microsoft Edge", "Microsoft Edge/NativeMessagingHosts"),
+        BrowserKind::Brave => (
+            "BraveSoftware/Brave-Browser",
+            "BraveSoftware/Brave-Browser/NativeMessagingHosts",
+        ),
+        BrowserKind::Opera => (
+            "com.operasoftware.Opera",
+            "com.operasoftware.Opera/NativeMessagingHosts",
+        ),
+        BrowserKind::Vivaldi => ("Vivaldi", "Vivaldi/NativeMessagingHosts"),
+        BrowserKind::Firefox => ("Firefox", "Mozilla/NativeMessagingHosts"),
+        BrowserKind::LibreWolf => ("librewolf", "LibreWolf/NativeMessagingHosts"),
+        BrowserKind::Waterfox => ("Waterfox", "Waterfox/NativeMessagingHosts"),
+    };
+
+    let application_support = base_dirs.home_dir().join("Library/Application Support");
+    vec![Location {
+        kind: LocationKind::User,
+        detect_dir: application_support.join(profile_dir),
+        manifest_path: application_support
+            .join(manifest_dir)
+            .join(manifest_file_name()),
+    }]
+}
+
+#[cfg(windows)]
+fn platform_locations(
+    base_dirs: &directories::BaseDirs,
+    browser: &BrowserKind,
+    system: bool,
+) -> Vec<Location> {
+    const CHROME_KEY: &str = r"SOFTWARE\Google\Chrome\NativeMessagingHosts";
+    const MOZILLA_KEY: &str = r"SOFTWARE\Mozilla\NativeMessagingHosts";
+
+    let local = base_dirs.data_local_dir();
+    let roaming = base_dirs.data_dir();
+    let (profile_dir, key) = match browser {
+        BrowserKind::Chrome => (local.join(r"Google\Chrome\User Data"), CHROME_KEY),
+        BrowserKind::ChromeBeta => (local.join(r"Google\Chrome Beta\User Data"), CHROME_KEY),
+        BrowserKind::ChromeDev => (local.join(r"Google\Chrome Dev\User Data"), CHROME_KEY),
+        BrowserKind::ChromeCanary => (local.join(r"Google\Chrome SxS\User Data"), CHROME_KEY),
+        BrowserKind::Chromium => (
+            local.join(r"Chromium\User Data"),
+            r"SOFTWARE\Chromium\NativeMessagingHosts",
+        ),
+        BrowserKind::Edge => (
+            local.join(r"Microsoft\Edge\User Data"),
+            r"SOFTWARE\Microsoft\Edge\NativeMessagingHosts",
+        ),
+        BrowserKind::Brave => (
+            local.join(r"BraveSoftware\Brave-Browser\User Data"),
+            r"SOFTWARE\BraveSoftware\Brave-Browser\NativeMessagingHosts",
+        ),
+        BrowserKind::Opera => (roaming.join(r"Opera Software\Opera Stable"), CHROME_KEY),
+        BrowserKind::Vivaldi => (local.join(r"Vivaldi\User Data"), CHROME_KEY),
+        BrowserKind::Firefox => (roaming.join(r"Mozilla\Firefox"), MOZILLA_KEY),
+        BrowserKind::LibreWolf => (roaming.join("librewolf"), MOZILLA_KEY),
+        BrowserKind::Waterfox => (roaming.join("Waterfox"), MOZILLA_KEY),
+    };
+
+    vec![Location {
+        kind: if system {
+            LocationKind::System
+        } else {
+            LocationKind::User
+        },
+        detect_dir: profile_dir,
+        registry_key: format!(r"{}\{}", key, crate::EDMAN_UNIQUE_NAME),
+    }]
+}
+
+/// The browser is considered installed if the parent of `manifest_dir` exists.
+#[cfg(unix)]
+fn system_location(manifest_dir: &str) -> Location {
+    let manifest_dir = std::path::Path::new(manifest_dir);
+    Location {
+        kind: LocationKind::System,
+        detect_dir: manifest_dir
+            .parent()
+            .map(|parent| parent.to_owned())
+            .unwrap_or_default(),
+        manifest_path: manifest_dir.join(manifest_file_name()),
+    }
+}
+
+#[cfg(unix)]
+fn manifest_file_name() -> String {
+    format!("{}.json", crate::EDMAN_UNIQUE_NAME)
+}
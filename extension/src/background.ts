@@ -1,35 +1,31 @@
 import { native } from "./native";
 import { registerOnMessage } from "./Messaging";
+import { DownloadState } from "./generated/ce-adapter";
 
-interface RegisterFileMessage {
-  downloadPath: string;
-  savePath: string[];
-  key: string;
-}
-const initiatedDownloads: Map<number, RegisterFileMessage> = new Map();
+// The browser does not emit changes of `bytesReceived`, so it is polled while something downloads
+const PROGRESS_INTERVAL_MS = 1000;
+let progressTimer: ReturnType<typeof setInterval> | undefined;
 
-registerOnMessage(async ({ type, data, callback }) => {
+registerOnMessage(async ({ type, data, sender, callback }) => {
   switch (type) {
     case "download": {
-      const config = await native.sendNativeMessage("config", undefined);
-
-      const downloadPath = `${
-        config.data.downloadSubdirectory
-      }/${Date.now()}-${data.key.replace(/[./\/]/, "_")}`;
-      const id = await chrome.downloads.download({
+      const cookies = await chrome.cookies.getAll({ url: data.url });
+      await native.enqueueDownload({
         url: data.url,
-        filename: downloadPath,
-      });
-      initiatedDownloads.set(id, {
-        downloadPath,
         savePath: data.savePath,
         key: data.key,
+        // The page that asked for the download, which its origin quota applies to
+        pageOrigin: sender.origin ?? (sender.tab?.url ? new URL(sender.tab.url).origin : undefined),
+        cookie:
+          cookies.length > 0
+            ? cookies.map(({ name, value }) => `${name}=${value}`).join("; ")
+            : undefined,
       });
       callback(undefined);
       break;
     }
     case "file_states": {
-      const res = await native.sendNativeMessage("fetch_file_states", {
+      const res = await native.fetchFileStates({
         query: data.keys,
       });
       callback({
@@ -40,19 +36,36 @@ registerOnMessage(async ({ type, data, callback }) => {
   }
 });
 
-chrome.downloads.onChanged.addListener(async (delta) => {
-  if (
-    delta.state?.previous !== "complete" &&
-    delta.state?.current === "complete"
-  ) {
-    const id = delta.id;
-    const message = initiatedDownloads.get(id);
-    if (!message) return;
+async function reportProgress(item: chrome.downloads.DownloadItem) {
+  await native.reportProgress({
+    downloadId: item.id,
+    url: item.finalUrl || item.url,
+    filename: item.filename,
+    bytesReceived: item.bytesReceived,
+    totalBytes: item.totalBytes,
+    state: item.state as DownloadState,
+    error: item.error,
+  });
+}
 
-    await native.sendNativeMessage("register_file", message);
-    await chrome.downloads.erase({
-      id,
-    });
-    initiatedDownloads.delete(id);
+async function pollProgress() {
+  const items = await chrome.downloads.search({ state: "in_progress" });
+  if (items.length === 0) {
+    clearInterval(progressTimer);
+    progressTimer = undefined;
   }
-});
+  await Promise.all(items.map(reportProgress));
+}
+
+async function onDownloadChanged(id: number) {
+  const [item] = await chrome.downloads.search({ id });
+  if (!item) return;
+
+  await reportProgress(item);
+  if (item.state === "in_progress" && progressTimer === undefined) {
+    progressTimer = setInterval(pollProgress, PROGRESS_INTERVAL_MS);
+  }
+}
+
+chrome.downloads.onCreated.addListener((item) => onDownloadChanged(item.id));
+chrome.downloads.onChanged.addListener((delta) => onDownloadChanged(delta.id));
@@ -0,0 +1,75 @@
+/*
+ Generated by the build script of edman-ce-adapter
+*/
+
+import { NativeMessageKinds, NativeResultKinds } from "./ce-adapter";
+
+/** `type` of every message the host understands */
+export type NativeType = NativeMessageKinds["type"];
+
+/** Data sent with each type of message */
+export interface NativeRequests {
+	hello: Extract<NativeMessageKinds, { type: "hello" }>["data"];
+	config: Extract<NativeMessageKinds, { type: "config" }>["data"];
+	fetch_file_states: Extract<NativeMessageKinds, { type: "fetch_file_states" }>["data"];
+	register_file: Extract<NativeMessageKinds, { type: "register_file" }>["data"];
+	register_files: Extract<NativeMessageKinds, { type: "register_files" }>["data"];
+	enqueue_download: Extract<NativeMessageKinds, { type: "enqueue_download" }>["data"];
+	report_progress: Extract<NativeMessageKinds, { type: "report_progress" }>["data"];
+}
+
+/** Reply to each type of message */
+export interface NativeReplies {
+	hello: Extract<NativeResultKinds, { type: "hello" }>;
+	config: Extract<NativeResultKinds, { type: "config" }>;
+	fetch_file_states: Extract<NativeResultKinds, { type: "fetch_file_states" }>;
+	register_file: Extract<NativeResultKinds, { type: "register_file" }>;
+	register_files: Extract<NativeResultKinds, { type: "register_files" }>;
+	enqueue_download: Extract<NativeResultKinds, { type: "enqueue_download" }>;
+	report_progress: Extract<NativeResultKinds, { type: "report_progress" }>;
+}
+
+/** Sends a message to the native host and resolves to its reply */
+export interface NativeTransport {
+	sendNativeMessage<T extends NativeType>(
+		type: T,
+		data: NativeRequests[T],
+	): Promise<NativeReplies[T]>;
+}
+
+/** One method for each type of message */
+export class NativeClient {
+	public constructor(private readonly transport: NativeTransport) {}
+
+	/** Sent by the extension before anything else */
+	public hello(data: NativeRequests["hello"]): Promise<NativeReplies["hello"]> {
+		return this.transport.sendNativeMessage("hello", data);
+	}
+
+	public config(): Promise<NativeReplies["config"]> {
+		return this.transport.sendNativeMessage("config", undefined);
+	}
+
+	public fetchFileStates(data: NativeRequests["fetch_file_states"]): Promise<NativeReplies["fetch_file_states"]> {
+		return this.transport.sendNativeMessage("fetch_file_states", data);
+	}
+
+	public registerFile(data: NativeRequests["register_file"]): Promise<NativeReplies["register_file"]> {
+		return this.transport.sendNativeMessage("register_file", data);
+	}
+
+	/** Several finished downloads at once, registered in one transaction */
+	public registerFiles(data: NativeRequests["register_files"]): Promise<NativeReplies["register_files"]> {
+		return this.transport.sendNativeMessage("register_files", data);
+	}
+
+	/** Lets edman fetch `url` itself instead of going through the browser's downloads */
+	public enqueueDownload(data: NativeRequests["enqueue_download"]): Promise<NativeReplies["enqueue_download"]> {
+		return this.transport.sendNativeMessage("enqueue_download", data);
+	}
+
+	/** Tells edman about a download the browser is fetching */
+	public reportProgress(data: NativeRequests["report_progress"]): Promise<NativeReplies["report_progress"]> {
+		return this.transport.sendNativeMessage("report_progress", data);
+	}
+}
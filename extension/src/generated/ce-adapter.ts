@@ -1,10 +1,21 @@
 export const EDMAN_UNIQUE_NAME = "io.github.femshima.edman";
+export const NATIVE_PROTOCOL_VERSION = 1;
 
 /*
  Generated by typeshare 1.7.0
 */
 
+export interface ServerInfoRequest {
+}
+
+export interface ServerInfoReply {
+	/** Version of edman-main */
+	version: string;
+}
+
 export interface ConfigRequest {
+	/** Caller identity used to pick a profile. The active profile is used if absent. */
+	origin?: string;
 }
 
 export interface Config {
@@ -13,21 +24,119 @@ export interface Config {
 	save_file_directory: string;
 	allowed_origins: string[];
 	allowed_extensions: string[];
+	collision_policy: number;
+	/** Maximum total size of the files saved with this profile. 0 means unlimited. */
+	save_quota_bytes: number;
+	origin_quotas: OriginQuota[];
+	/** Usage ratio at which a quota warning is emitted */
+	quota_warning_percent: number;
+}
+
+/** Maximum total size of the files downloaded from the pages of one origin */
+export interface OriginQuota {
+	/** e.g. https://example.com */
+	origin: string;
+	bytes: number;
+}
+
+/** A named configuration. Exactly one profile is active at a time. */
+export interface Profile {
+	name: string;
+	active: boolean;
+	config?: Config;
 }
 
 export interface ConfigReply {
 	config?: Config;
+	profile: string;
+	/** Every profile, so that installed manifests can allow all of them */
+	profiles: Profile[];
 }
 
 export interface RegisterFileRequest {
 	path: string;
 	key: string;
+	origin?: string;
+	/** Page the file was downloaded from, which origin quotas apply to */
+	page_origin?: string;
 }
 
 export interface RegisterFileReply {
 	id: number;
 }
 
+/** Moves a finished download into the save directory and registers it */
+export interface CommitDownloadRequest {
+	/** Relative to the download directory */
+	download_path: string;
+	/** Path components relative to the save directory */
+	save_path: string[];
+	key: string;
+	origin?: string;
+	/** Page the file was downloaded from, which origin quotas apply to */
+	page_origin?: string;
+}
+
+export interface CommitDownloadReply {
+	/** Not set if the file was skipped because of a collision */
+	id?: number;
+	/** Path the file was actually saved to, relative to the save directory */
+	path: string;
+}
+
+export interface RegisterFilesRequest {
+	files: RegisterFilesItem[];
+	origin?: string;
+}
+
+/** Same as CommitDownloadRequest, with the origin of the whole batch */
+export interface RegisterFilesItem {
+	download_path: string;
+	save_path: string[];
+	key: string;
+	page_origin?: string;
+}
+
+export interface RegisterFilesReply {
+	/** One for each file, in the order of the request */
+	results: RegisterFilesResult[];
+}
+
+/** Exactly one of the fields is set */
+export interface RegisterFilesResult {
+	file?: CommitDownloadReply;
+	/** Why this file could not be committed. The others are not affected. */
+	error?: FileError;
+}
+
+export interface FileError {
+	/** gRPC status code, as if the file had been committed on its own */
+	code: number;
+	message: string;
+}
+
+/** Lets edman-main fetch a file itself and register it once it is complete */
+export interface EnqueueDownloadRequest {
+	/** http or https */
+	url: string;
+	/** Path components relative to the save directory */
+	save_path: string[];
+	key: string;
+	origin?: string;
+	/** Cookie header to send along, as forwarded by the extension */
+	cookie?: string;
+	/** Hex encoded SHA-256 the content has to match */
+	sha256?: string;
+	/** Jobs with a higher priority start first */
+	priority: number;
+	/** Page the file is downloaded from, which origin quotas apply to */
+	page_origin?: string;
+}
+
+export interface EnqueueDownloadReply {
+	job_id: number;
+}
+
 export interface GetFileStatesRequest {
 	keys: string[];
 }
@@ -36,7 +145,126 @@ export interface GetFileStatesReply {
 	result: boolean[];
 }
 
+/** Progress of a download that the browser is fetching itself */
+export interface ReportProgressRequest {
+	/** Id the browser assigned to the download */
+	download_id: number;
+	url: string;
+	/** Absolute path the browser writes to */
+	filename: string;
+	origin?: string;
+	bytes_received: number;
+	/** 0 if the size is unknown */
+	total_bytes: number;
+	state: number;
+	/** Why the download was interrupted */
+	error?: string;
+}
+
+export interface ReportProgressReply {
+}
+
+export interface RegisterManifestRequest {
+	/** Name of the browser, as passed to --install */
+	browser: string;
+	/** Absolute path to the manifest file that edman-ce-adapter wrote */
+	path: string;
+	kind: number;
+}
+
+export interface RegisterManifestReply {
+}
+
+/** One file of a `register_files` message, same as the data of `register_file` */
+export interface FileToRegister {
+	downloadPath: string;
+	savePath: string[];
+	key: string;
+	/** Page the file was downloaded from, which origin quotas apply to */
+	pageOrigin?: string;
+}
+
+export interface DownloadProgress {
+	/** Id the browser assigned to the download */
+	downloadId: number;
+	url: string;
+	/** Absolute path the browser writes to */
+	filename: string;
+	bytesReceived: number;
+	/** 0 if the size is unknown */
+	totalBytes: number;
+	state: DownloadState;
+	/** Why the download was interrupted */
+	error?: string;
+}
+
+export interface NativeError {
+	code: NativeErrorCode;
+	message: string;
+	/** Whether sending the same message again may succeed */
+	retryable: boolean;
+	details?: string;
+}
+
+/** What to do when a registered file would land on an existing path */
+export enum CollisionPolicy {
+	Overwrite = "Overwrite",
+	AutoSuffix = "AutoSuffix",
+	HashSuffix = "HashSuffix",
+	Skip = "Skip",
+	Fail = "Fail",
+}
+
+/** Mirrors the state of a browser download */
+export enum ProgressState {
+	InProgress = "InProgress",
+	Interrupted = "Interrupted",
+	Complete = "Complete",
+}
+
+/** Which allow-list a manifest carries */
+export enum ManifestKind {
+	Chromium = "Chromium",
+	Firefox = "Firefox",
+}
+
+/** Stable identifiers the extension can branch on */
+export enum NativeErrorCode {
+	/** The calling extension is not in the allow-list of the profile */
+	OriginNotAllowed = "origin_not_allowed",
+	/** The extension and the native host cannot talk to each other */
+	ProtocolMismatch = "protocol_mismatch",
+	/** The message has a `type` the native host does not know */
+	UnknownMessage = "unknown_message",
+	/** The message could not be parsed */
+	InvalidMessage = "invalid_message",
+	/** A download or save path is outside of the allowed directories */
+	PathInvalid = "path_invalid",
+	/** A value of the message is not acceptable, e.g. a malformed URL */
+	InvalidArgument = "invalid_argument",
+	NotFound = "not_found",
+	AlreadyExists = "already_exists",
+	PermissionDenied = "permission_denied",
+	QuotaExceeded = "quota_exceeded",
+	/** edman-main cannot be reached */
+	BackendUnavailable = "backend_unavailable",
+	IoError = "io_error",
+	Internal = "internal",
+}
+
+/** State of a browser download, as in `chrome.downloads.State` */
+export enum DownloadState {
+	InProgress = "in_progress",
+	Interrupted = "interrupted",
+	Complete = "complete",
+}
+
 export type NativeMessageKinds = 
+	/** Sent by the extension before anything else */
+	| { type: "hello", data: {
+	protocolVersion: number;
+	features: string[];
+}}
 	| { type: "config", data?: undefined }
 	| { type: "fetch_file_states", data: {
 	query: string[];
@@ -45,13 +273,80 @@ export type NativeMessageKinds =
 	downloadPath: string;
 	savePath: string[];
 	key: string;
-}};
+	/** Page the file was downloaded from, which origin quotas apply to */
+	pageOrigin?: string;
+}}
+	/** Several finished downloads at once, registered in one transaction */
+	| { type: "register_files", data: {
+	files: FileToRegister[];
+}}
+	/** Lets edman fetch `url` itself instead of going through the browser's downloads */
+	| { type: "enqueue_download", data: {
+	url: string;
+	savePath: string[];
+	key: string;
+	/** `Cookie` header for `url` */
+	cookie?: string;
+	/** Hex encoded SHA-256 the content has to match */
+	sha256?: string;
+	/** Jobs with a higher priority start first */
+	priority?: number;
+	/** Page that links to `url`, which origin quotas apply to */
+	pageOrigin?: string;
+}}
+	/** Tells edman about a download the browser is fetching */
+	| { type: "report_progress", data: DownloadProgress };
 
 export type NativeResultKinds = 
+	| { type: "hello", data: {
+	protocolVersion: number;
+	/** Version of edman-ce-adapter */
+	adapterVersion: string;
+	/** Version of edman-main */
+	serverVersion: string;
+	/** `type`s of the messages the host understands */
+	messageKinds: string[];
+	/** Features requested by the extension that the host supports */
+	features: string[];
+}}
 	| { type: "config", data: {
 	downloadSubdirectory: string;
 }}
 	| { type: "fetch_file_states", data: GetFileStatesReply }
-	| { type: "register_file", data: RegisterFileReply }
-	| { type: "err", data: string };
+	| { type: "register_file", data: {
+	/** `None` if the file was skipped because of a collision */
+	id?: number;
+	/** Path the file was actually saved to, relative to the save directory */
+	path: string;
+}}
+	/** One result for each file, in the order of the request */
+	| { type: "register_files", data: {
+	results: RegisteredFile[];
+}}
+	/** The download has been queued and continues in the background */
+	| { type: "enqueue_download", data: {
+	jobId: number;
+}}
+	| { type: "report_progress", data?: undefined }
+	/**
+	 * One part of a reply that does not fit into a single message.
+	 * 
+	 * Replies longer than 1 MB are sent as `count` chunks with the id of the
+	 * request, in order of `index`. Concatenating their `payload`s gives the
+	 * JSON of the complete reply.
+	 */
+	| { type: "chunk", data: {
+	index: number;
+	count: number;
+	payload: string;
+}}
+	| { type: "err", data: NativeError };
 
+/** Result of one file of a `register_files` message */
+export type RegisteredFile = 
+	/** Same as the reply to `register_file` */
+	| { type: "ok", data: {
+	id?: number;
+	path: string;
+}}
+	| { type: "err", data: NativeError };
@@ -1,23 +1,42 @@
 import {
   EDMAN_UNIQUE_NAME,
+  NATIVE_PROTOCOL_VERSION,
+  NativeError,
+  NativeErrorCode,
   NativeMessageKinds,
   NativeResultKinds,
 } from "./generated/ce-adapter";
+import {
+  NativeClient,
+  NativeReplies,
+  NativeRequests,
+  NativeTransport,
+  NativeType,
+} from "./generated/ce-adapter-client";
 
 interface WithId {
   id: string;
 }
 
-type NativeMessage = NativeMessageKinds & WithId;
 type NativeResult = NativeResultKinds & WithId;
 
-type With<T, U> = U extends { type: T } ? U : never;
+const FEATURES = ["chunked_replies"];
 
-type NativeTypes = NativeMessage["type"];
-type INativeMessage<T extends NativeTypes> = With<T, NativeMessageKinds>;
-type INativeResult<T extends NativeTypes> = With<T, NativeResultKinds>;
+export class NativeHostError extends Error {
+  public readonly code: NativeErrorCode;
+  public readonly retryable: boolean;
+  public readonly details?: string;
 
-export class NativeMessaging {
+  public constructor(error: NativeError) {
+    super(`Native process returned error: ${error.message}`);
+    this.name = "NativeHostError";
+    this.code = error.code;
+    this.retryable = error.retryable;
+    this.details = error.details;
+  }
+}
+
+export class NativeMessaging implements NativeTransport {
   private port?: chrome.runtime.Port;
   private listeners = {
     onDisconnect: this.onDisconnect.bind(this),
@@ -25,6 +44,8 @@ export class NativeMessaging {
   };
   private callbacks: Map<string, (result: NativeResultKinds) => void> =
     new Map();
+  private chunks: Map<string, string[]> = new Map();
+  private handshake?: Promise<NativeReplies["hello"]>;
   private counter = 0;
 
   public constructor() {
@@ -43,11 +64,39 @@ export class NativeMessaging {
     this.port?.onDisconnect.removeListener(this.listeners.onDisconnect);
     this.port?.onMessage.removeListener(this.listeners.onMessage);
     this.port = undefined;
+    this.chunks.clear();
+    this.handshake = undefined;
+
+    // Hosts that cannot parse a message exit instead of replying
+    const reason = chrome.runtime.lastError?.message ?? "unknown reason";
+    for (const callback of this.callbacks.values()) {
+      callback({
+        type: "err",
+        data: {
+          code: NativeErrorCode.BackendUnavailable,
+          message: `Native host disconnected: ${reason}`,
+          retryable: true,
+        },
+      });
+    }
+    this.callbacks.clear();
   }
   private onMessage(message: NativeResult) {
     const id = message.id;
     if (!id) return;
 
+    if (message.type === "chunk") {
+      const { index, count, payload } = message.data;
+      const parts = this.chunks.get(id) ?? [];
+      parts[index] = payload;
+      this.chunks.set(id, parts);
+      if (parts.filter((part) => part !== undefined).length < count) return;
+
+      this.chunks.delete(id);
+      this.onMessage(JSON.parse(parts.join("")));
+      return;
+    }
+
     const reply = {
       ...message,
       id: undefined,
@@ -71,16 +120,40 @@ export class NativeMessaging {
     });
   }
 
-  public async sendNativeMessage<T extends NativeTypes>(
+  private hello() {
+    if (!this.handshake) {
+      const handshake = this.request("hello", {
+        protocolVersion: NATIVE_PROTOCOL_VERSION,
+        features: FEATURES,
+      });
+      handshake.catch(() => {
+        if (this.handshake === handshake) this.handshake = undefined;
+      });
+      this.handshake = handshake;
+    }
+    return this.handshake;
+  }
+
+  public async sendNativeMessage<T extends NativeType>(
+    type: T,
+    data: NativeRequests[T],
+  ): Promise<NativeReplies[T]> {
+    if (type !== "hello") {
+      await this.hello();
+    }
+    return this.request(type, data);
+  }
+
+  private async request<T extends NativeType>(
     type: T,
-    data: INativeMessage<T>["data"],
-  ): Promise<INativeResult<T>> {
-    const result = await this.send({ type, data } as INativeMessage<T>);
+    data: NativeRequests[T],
+  ): Promise<NativeReplies[T]> {
+    const result = await this.send({ type, data } as NativeMessageKinds);
 
     if (result.type === type) {
-      return result as INativeResult<T>;
+      return result as NativeReplies[T];
     } else if (result.type === "err") {
-      throw new Error(`Native process returned error: ${result.data}`);
+      throw new NativeHostError(result.data);
     } else {
       throw new Error(
         `Return type mismatch: expected ${type} but got ${result.type}`,
@@ -89,4 +162,4 @@ export class NativeMessaging {
   }
 }
 
-export const native = new NativeMessaging();
+export const native = new NativeClient(new NativeMessaging());
@@ -0,0 +1,2 @@
+-- AlterTable
+ALTER TABLE "Config" ADD COLUMN "collision_policy" INTEGER NOT NULL DEFAULT 0;
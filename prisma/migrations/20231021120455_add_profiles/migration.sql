@@ -0,0 +1,25 @@
+-- AlterTable
+ALTER TABLE "File" ADD COLUMN "profile" TEXT NOT NULL DEFAULT 'default';
+
+-- RedefineTables
+PRAGMA foreign_keys=OFF;
+CREATE TABLE "new_Config" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "name" TEXT NOT NULL DEFAULT 'default',
+    "active" BOOLEAN NOT NULL DEFAULT false,
+    "download_directory" TEXT NOT NULL,
+    "download_subdirectory" TEXT NOT NULL,
+    "save_file_directory" TEXT NOT NULL,
+    "allowed_extensions" TEXT NOT NULL,
+    "allowed_origins" TEXT NOT NULL,
+    "collision_policy" INTEGER NOT NULL DEFAULT 0
+);
+INSERT INTO "new_Config" ("allowed_extensions", "allowed_origins", "collision_policy", "download_directory", "download_subdirectory", "id", "save_file_directory") SELECT "allowed_extensions", "allowed_origins", "collision_policy", "download_directory", "download_subdirectory", "id", "save_file_directory" FROM "Config";
+DROP TABLE "Config";
+ALTER TABLE "new_Config" RENAME TO "Config";
+CREATE UNIQUE INDEX "Config_name_key" ON "Config"("name");
+PRAGMA foreign_key_check;
+PRAGMA foreign_keys=ON;
+
+-- The former singleton config becomes the active default profile
+UPDATE "Config" SET "active" = true WHERE "id" = 0;
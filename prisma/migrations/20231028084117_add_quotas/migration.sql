@@ -0,0 +1,9 @@
+-- AlterTable
+ALTER TABLE "Config" ADD COLUMN "save_quota_bytes" BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE "Config" ADD COLUMN "origin_quotas" TEXT NOT NULL DEFAULT '';
+ALTER TABLE "Config" ADD COLUMN "quota_warning_percent" INTEGER NOT NULL DEFAULT 90;
+
+-- AlterTable
+ALTER TABLE "File" ADD COLUMN "origin" TEXT NOT NULL DEFAULT '';
+ALTER TABLE "File" ADD COLUMN "page_origin" TEXT NOT NULL DEFAULT '';
+ALTER TABLE "File" ADD COLUMN "size" BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,17 @@
+-- CreateTable
+CREATE TABLE "DownloadJob" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "createdAt" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "url" TEXT NOT NULL,
+    "save_path" TEXT NOT NULL,
+    "key" TEXT NOT NULL,
+    "profile" TEXT NOT NULL,
+    "host" TEXT NOT NULL,
+    "origin" TEXT NOT NULL DEFAULT '',
+    "page_origin" TEXT NOT NULL DEFAULT '',
+    "sha256" TEXT NOT NULL DEFAULT '',
+    "state" INTEGER NOT NULL DEFAULT 0,
+    "priority" INTEGER NOT NULL DEFAULT 0,
+    "attempts" INTEGER NOT NULL DEFAULT 0,
+    "last_error" TEXT NOT NULL DEFAULT ''
+);
@@ -0,0 +1,11 @@
+-- CreateTable
+CREATE TABLE "InstalledManifest" (
+    "id" INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    "createdAt" DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "browser" TEXT NOT NULL,
+    "path" TEXT NOT NULL,
+    "kind" INTEGER NOT NULL DEFAULT 0
+);
+
+-- CreateIndex
+CREATE UNIQUE INDEX "InstalledManifest_browser_path_key" ON "InstalledManifest"("browser", "path");